@@ -19,6 +19,14 @@ impl<'de> Deserialize<'de> for Obj {
     }
 }
 
+/// A function along with the values it captured, which it receives ahead
+/// of its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub name: String,
+    pub captured: Vec<InterpreterType>
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
 pub enum InterpreterType {
@@ -28,7 +36,10 @@ pub enum InterpreterType {
     string(String),
     Array(Vec<InterpreterType>),
     Object(Obj),
-    None
+    None,
+    /// Only exists while a program runs, so it's never read from or written to JSON.
+    #[serde(skip)]
+    Function(Closure)
 } 
 
 
@@ -62,6 +73,10 @@ impl Hash for InterpreterType {
                 }
             },
             InterpreterType::None => {
+            },
+            InterpreterType::Function(f) => {
+                state.write(f.name.as_bytes());
+                f.captured.hash(state);
             }
         };
 
@@ -98,6 +113,15 @@ impl InterpreterType {
         }
     }
 
+    pub fn to_index(&self) -> Result<usize, String> {
+        match self {
+            InterpreterType::int(i) if *i >= 0 => Ok(*i as usize),
+            InterpreterType::double(d) if *d >= 0.0 && d.fract() == 0.0 => Ok(*d as usize),
+            InterpreterType::double(d) => Err(format!("Cannot index array with {}", d)),
+            _ => Err("Cannot index array with type".to_string())
+        }
+    }
+
     pub fn try_push(&mut self, data: InterpreterType) -> Result<(), String> {
         match self {
             InterpreterType::Array(r) => {r.push(data); Ok(())},
@@ -113,7 +137,7 @@ impl InterpreterType {
             },
             InterpreterType::Array(a) => match field {
                 InterpreterType::int(i) =>a.get_mut(i as usize),
-                InterpreterType::double(d) => a.get_mut(d as usize),
+                InterpreterType::double(_) => a.get_mut(field.to_index()?),
                 _ => return Err(format!("Cannot index array with type"))
             },
             _ => return Err(format!("cannot index into type"))
@@ -134,6 +158,13 @@ impl InterpreterType {
                 InterpreterType::string(s) => o.0.insert(s, set_to),
                 _ => return Err(format!("Cannot index object with this type"))
             },
+            InterpreterType::Array(a) => {
+                let i = last_field.to_index()?;
+                match a.get_mut(i) {
+                    Some(v) => Some(std::mem::replace(v, set_to)),
+                    None => return Err(format!("Index {} is out of bounds for an array of length {}", i, a.len()))
+                }
+            },
             _ => return Err(format!("cannot overwrite type"))
         };
         Ok(())
@@ -144,7 +175,12 @@ impl InterpreterType {
             (InterpreterType::string(s1), InterpreterType::string(s2)) => s1 == s2,
             (InterpreterType::int(i1), InterpreterType::int(i2)) => i1 == i2,
             (InterpreterType::double(d1), InterpreterType::double(d2)) => d1 == d2,
+            // Numbers compare by value, as they do in `compare`.
+            (InterpreterType::int(i), InterpreterType::double(d)) |
+            (InterpreterType::double(d), InterpreterType::int(i)) => *i as f64 == *d,
+            (InterpreterType::bool(b1), InterpreterType::bool(b2)) => b1 == b2,
             (InterpreterType::None, InterpreterType::None) => true,
+            (InterpreterType::Function(f1), InterpreterType::Function(f2)) => f1 == f2,
             (_, _) => false
         }
     }
@@ -170,7 +206,7 @@ impl InterpreterType {
     pub fn plus(&self, other: &InterpreterType) -> Result<InterpreterType, String> {
         Ok(match self {
             InterpreterType::int(i1) => match other {
-                InterpreterType::int(i2) => InterpreterType::int(i1 + i2),
+                InterpreterType::int(i2) => checked(i1.checked_add(*i2))?,
                 InterpreterType::double(d2) => InterpreterType::double(*i1 as f64 + d2),
                 InterpreterType::string(s) => InterpreterType::string(format!("{}{}", i1, s)),
                 _ => return Err(format!("not addable"))
@@ -194,7 +230,7 @@ impl InterpreterType {
     pub fn minus(&self, other: &InterpreterType) -> Result<InterpreterType, String> {
         Ok(match self {
             InterpreterType::int(i1) => match other {
-                InterpreterType::int(i2) => InterpreterType::int(i1 - i2),
+                InterpreterType::int(i2) => checked(i1.checked_sub(*i2))?,
                 InterpreterType::double(d2) => InterpreterType::double(*i1 as f64 - d2),
                 _ => return Err(format!("not subtractable"))
             },
@@ -210,7 +246,8 @@ impl InterpreterType {
     pub fn divide(&self, other: &InterpreterType) -> Result<InterpreterType, String> {
         Ok(match self {
             InterpreterType::int(i1) => match other {
-                InterpreterType::int(i2) => InterpreterType::int(i1 / i2),
+                InterpreterType::int(0) => return Err(format!("Division by zero")),
+                InterpreterType::int(i2) => checked(i1.checked_div(*i2))?,
                 InterpreterType::double(d2) => InterpreterType::double(*i1 as f64 / d2),
                 _ => return Err(format!("not divisible"))
             },
@@ -226,7 +263,7 @@ impl InterpreterType {
     pub fn multiply(&self, other: &InterpreterType) -> Result<InterpreterType, String> {
         Ok(match self {
             InterpreterType::int(i1) => match other {
-                InterpreterType::int(i2) => InterpreterType::int(i1 * i2),
+                InterpreterType::int(i2) => checked(i1.checked_mul(*i2))?,
                 InterpreterType::double(d2) => InterpreterType::double(*i1 as f64 * d2),
                 _ => return Err(format!("cannot multiply"))
             },
@@ -238,8 +275,32 @@ impl InterpreterType {
             _ => return Err(format!("cannot multiply"))
         })
     }
+
+    /// Whether this is a function or holds one anywhere inside it.
+    pub fn contains_function(&self) -> bool {
+        match self {
+            InterpreterType::Function(_) => true,
+            InterpreterType::Array(a) => a.iter().any(|v| v.contains_function()),
+            InterpreterType::Object(o) => o.0.values().any(|v| v.contains_function()),
+            _ => false
+        }
+    }
+
+    pub fn negate(&self) -> Result<InterpreterType, String> {
+        Ok(match self {
+            InterpreterType::int(i) => checked(i.checked_neg())?,
+            InterpreterType::double(d) => InterpreterType::double(-d),
+            _ => return Err(format!("cannot negate"))
+        })
+    }
 }
 
+fn checked(result: Option<i64>) -> Result<InterpreterType, String> {
+    match result {
+        Some(i) => Ok(InterpreterType::int(i)),
+        None => Err("Integer overflow".to_string())
+    }
+}
 
 pub enum Compare {
     Less,
@@ -7,10 +7,13 @@ use ops::Runner;
 use crate::data::{InterpreterType};
 use crate::ops::{Op};
 use crate::schemas::Schema;
+use crate::roles::Keyring;
 
 pub mod data;
 pub mod schemas;
 pub mod ops;
+pub mod roles;
+pub mod verify;
 
 pub struct Execution<'a> {
     pub next_op_index: usize,
@@ -66,8 +69,8 @@ impl <'a> Context<'a>  {
 pub struct Globals<'a> {
     pub schemas: &'a HashMap<String, Schema>, 
     pub fns: &'a HashMap<String, Vec<Op>>,
-    pub private_key: &'a[u8; 64],
-    pub public_key: &'a [u8; 32]
+    pub lambdas: &'a HashMap<String, Vec<Op>>,
+    pub keys: Keyring
 }
 
 pub struct State<'a> {
@@ -111,25 +114,33 @@ impl<'a> State<'a> {
         
     }
     
-    pub fn set_field(&mut self, arg_id: usize, fields: Vec<InterpreterType>, value: InterpreterType) {
+    pub fn set_field(&mut self, arg_id: usize, fields: Vec<InterpreterType>, value: InterpreterType) -> Result<(), String> {
         let abs = self.abs_addr(arg_id);
-        self.state[abs].set(fields, value).unwrap();
+        self.state[abs].set(fields, value)
     }
 
-    pub fn delete(&mut self, arg_id: usize, mut fields: Vec<InterpreterType>) {        
-        let last_field = fields.pop().unwrap();
+    pub fn delete(&mut self, arg_id: usize, mut fields: Vec<InterpreterType>) -> Result<(), String> {        
+        let last_field = fields.pop().safe_unwrap()?;
         let id = self.abs_addr(arg_id);
-        let mut o_or_a = self.state.get_mut(id).unwrap();
+        let mut o_or_a = self.state.get_mut(id).safe_unwrap()?;
         for f in fields {
-            o_or_a =  o_or_a.get(f).unwrap().unwrap();
+            o_or_a =  o_or_a.get(f)?.safe_unwrap()?;
         }
         match o_or_a {
             InterpreterType::Object(o) => match last_field {
                 InterpreterType::string(s) => o.0.remove(&s),
-                _ => panic!("Cannot index object with this type")
+                _ => return Err(format!("Cannot index object with this type"))
             },
-            _ => panic!("cannot delete type")
+            InterpreterType::Array(a) => {
+                let i = last_field.to_index()?;
+                if i >= a.len() {
+                    return Err(format!("Cannot delete index {} of an array of length {}", i, a.len()));
+                }
+                Some(a.remove(i))
+            },
+            _ => return Err(format!("cannot delete type"))
         };
+        Ok(())
     }
 
     pub fn drop(&mut self, to_drop: usize) {
@@ -145,13 +156,13 @@ impl<'a> State<'a> {
         self.state.push(data);
     }
 
-    pub fn pushToArray(&mut self, arg_id: usize, data: InterpreterType, fields: Vec<InterpreterType>) {
+    pub fn pushToArray(&mut self, arg_id: usize, data: InterpreterType, fields: Vec<InterpreterType>) -> Result<(), String> {
         let id = self.abs_addr(arg_id);
-        let mut o_or_a = self.state.get_mut(id).unwrap();
+        let mut o_or_a = self.state.get_mut(id).safe_unwrap()?;
         for f in fields {
-            o_or_a =  o_or_a.get(f).unwrap().unwrap();
+            o_or_a =  o_or_a.get(f)?.safe_unwrap()?;
         }
-        o_or_a.try_push(data).unwrap();
+        o_or_a.try_push(data)
     }
 
     pub fn sizeOfScope(&self) -> usize {
@@ -178,22 +189,46 @@ impl<'a> State<'a> {
 
 
 impl<'a>  Globals<'a> {
+    /// Verifies `fns` and `lambdas` before anything can run them. Build this
+    /// once per loaded program and reuse it for every call.
     pub fn new(
         schemas: &'a HashMap<String, Schema>, 
         fns: &'a HashMap<String, Vec<Op>>,
+        lambdas: &'a HashMap<String, Vec<Op>>,
         private_key: &'a[u8; 64],
-        public_key: &'a[u8; 32]) -> Self {
-            Globals {
+        public_key: &'a[u8; 32]) -> Result<Self, String> {
+            verify::verify(fns, lambdas).map_err(|reason| format!("Program failed verification: {}", reason))?;
+            Ok(Globals {
                 schemas,
                 fns,
-                private_key,
-                public_key
-            }
+                lambdas,
+                keys: Keyring::new(*private_key, *public_key)
+            })
+    }
+
+    /// Signs new role instances with this key pair, while instances signed
+    /// with the previous key keep verifying until they expire.
+    pub fn rotate_key(&mut self, private_key: [u8; 64], public_key: [u8; 32]) {
+        self.keys.rotate(private_key, public_key);
+    }
+
+    /// Accepts role instances from the first signing scheme, which never expire,
+    /// until `until` seconds since the unix epoch.
+    pub fn accept_legacy_roles_until(&mut self, until: i64) {
+        self.keys.legacy_until = Some(until);
     }
     pub fn run(&'a self, fname: &String, state: &'a mut State<'a>) -> Result<InterpreterType, String> {
-    
-        let context = Context::new(self.fns.get(fname).unwrap());
-        Runner::new(self, state).run(context)
+        let ops = match self.fns.get(fname) {
+            Some(ops) => ops,
+            None => return Err(format!("Unknown function {}", fname))
+        };
+        let context = Context::new(ops);
+        let result = Runner::new(self, state).run(context)?;
+        // Callers get results as JSON, which functions can't be written to.
+        if result.contains_function() {
+            return Err(format!("{} returned a function, which can't leave the program", fname))
+        }
+        Ok(result)
     }
 }
 
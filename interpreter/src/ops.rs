@@ -1,18 +1,17 @@
 
     
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use serde::{Deserialize};
-use std::collections::hash_map::DefaultHasher;
-use crypto::ed25519;
-use std::hash::{Hash, Hasher};
+use serde::{Deserialize, Serialize};
 
 use crate::data::*;
 
 use crate::schemas::{Schema};
+use crate::roles;
 use crate::{Context, Globals, ContextState, State};
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(tag = "kind", content= "data")]
 pub enum Op {
     negatePrev,
@@ -42,7 +41,8 @@ pub enum Op {
     popStack,
     instantiate(InterpreterType),
     popArray,
-    flattenArray,
+    /// Replaces a `[key, value]` pair with the value and then the key.
+    unpackPair,
     toBool,
     moveStackToHeapArray(u64),
     arrayPush,
@@ -52,6 +52,7 @@ pub enum Op {
     ndArrayLen,
     setNestedField(Vec<String>),
     enforceSchemaInstanceOnHeap{schema: Schema, heap_pos: u64},
+    enforceSchemaInstanceOnStack(Schema),
     extractFields(Vec<Vec<String>>),
     equal,
     less,
@@ -64,8 +65,34 @@ pub enum Op {
     nMinus,
     nDivide,
     nMult,
+    nNegate,
     getKeys,
+    toIterable{entries: bool},
     invoke{name: String, args: u64},
+    /// Pops `captures` values and pushes a closure of the function `name` holding them.
+    makeClosure{name: String, captures: u64},
+    /// Pops `args` arguments and then the closure to call with them.
+    invokeValue{args: u64},
+    // Array methods pop their arguments and then the array.
+    arrayMap,
+    arrayFilter,
+    /// Takes a function and the initial value to fold the array into.
+    arrayReduce,
+    /// Takes a function returning a negative number when its first argument
+    /// goes first, or sorts numbers and strings in order without one.
+    arraySort{comparator: bool},
+    /// Pushes the first element the function holds for, or none.
+    arrayFind,
+    arrayAny,
+    arrayAll,
+    /// Takes a start index and, optionally, an end index.
+    arraySlice{end: bool},
+    arrayConcat,
+    arrayReverse,
+    /// Pushes the index of the first element equal to the argument, or -1.
+    arrayIndexOf,
+    /// Replaces elements that are arrays with their elements.
+    arrayFlatten,
     signRole,
     getType
 }    
@@ -89,6 +116,54 @@ impl<'a> Context<'a> {
 }
 
 
+/// Object entries ordered by key, so iteration order doesn't depend on hashing.
+fn sorted_entries(obj: HashMap<String, InterpreterType>) -> Vec<(String, InterpreterType)> {
+    let mut entries: Vec<(String, InterpreterType)> = obj.into_iter().collect();
+    entries.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+    entries
+}
+
+/// How arrays sort without a comparator.
+fn natural_order(a: &InterpreterType, b: &InterpreterType) -> Result<Compare, String> {
+    match (a, b) {
+        (InterpreterType::string(a), InterpreterType::string(b)) => Ok(match a.cmp(b) {
+            Ordering::Less => Compare::Less,
+            Ordering::Greater => Compare::Greater,
+            Ordering::Equal => Compare::Equal
+        }),
+        (InterpreterType::string(_), _) | (_, InterpreterType::string(_)) => {
+            Err("Can only sort numbers or strings without a comparator".to_string())
+        },
+        _ => a.compare(b).map_err(|_| "Can only sort numbers or strings without a comparator".to_string())
+    }
+}
+
+/// A stable sort that stops at the first comparison that fails. Comparators
+/// are user code, so they may not be a total order; that only scrambles the
+/// result rather than panicking like `sort_by` can.
+fn merge_sort<T>(mut items: Vec<T>, compare: &mut impl FnMut(&T, &T) -> Result<Compare, String>) -> Result<Vec<T>, String> {
+    if items.len() < 2 {
+        return Ok(items)
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, compare)?;
+    let right = merge_sort(right, compare)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Ties take from the left, which keeps equal elements in order.
+        let next = match compare(l, r)? {
+            Compare::Greater => right.next(),
+            _ => left.next()
+        };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
 pub struct Runner<'a> {
     globals: &'a Globals<'a>,
     state: &'a mut State<'a>,
@@ -116,7 +191,7 @@ impl<'a> Runner<'a> {
                     Some(schema) => schema.adheres(
                         &context.pop_stack()?,
                         self.globals.schemas,
-                        self.globals.public_key),
+                        &self.globals.keys),
                     None => return Err(format!("Schema does not exist"))
                 };
                 context.stack.push(InterpreterType::bool(b));
@@ -163,7 +238,7 @@ impl<'a> Runner<'a> {
             Op::setSavedField{index, field_depth} => {                
                 let set_to = context.pop_stack()?;
                 let fields = context.stack.split_off(context.stack.len() - *field_depth as usize);
-                self.state.set_field(*index as usize, fields, set_to);
+                self.state.set_field(*index as usize, fields, set_to)?;
                 context.advance()
             },
             Op::stringConcat{nStrings, joiner} => {
@@ -197,13 +272,13 @@ impl<'a> Runner<'a> {
             },
             Op::deleteSavedField{field_depth, index} => {       
                 let fields = context.stack.split_off(context.stack.len() - *field_depth as usize);                
-                self.state.delete(*index as usize, fields);                
+                self.state.delete(*index as usize, fields)?;                
                 context.advance()
             },
             Op::pushSavedField{field_depth, index} => {                
                 let push = context.pop_stack()?;
                 let fields = context.stack.split_off(context.stack.len() - *field_depth as usize);            
-                self.state.pushToArray(*index as usize, push, fields);                
+                self.state.pushToArray(*index as usize, push, fields)?;                
                 context.advance()
         
             },
@@ -258,7 +333,7 @@ impl<'a> Runner<'a> {
                     self.state.get_var(*heap_pos as usize, vec![])?;
                 
                 let s = self.globals.schemas.get(schema).safe_unwrap()?;
-                context.stack.push(InterpreterType::bool(s.adheres(&v, self.globals.schemas, self.globals.public_key)));
+                context.stack.push(InterpreterType::bool(s.adheres(&v, self.globals.schemas, &self.globals.keys)));
                 context.advance()
             },
             Op::moveStackTopToHeap => {                
@@ -285,10 +360,15 @@ impl<'a> Runner<'a> {
                 context.stack.push(res);
                 context.advance()        
             },
-            Op::flattenArray => {                
-                let mut res = context.pop_stack()?.to_array()?;
-                res.reverse();
-                context.stack.append(&mut res);
+            Op::unpackPair => {
+                let mut pair = context.pop_stack()?.to_array()?;
+                if pair.len() != 2 {
+                    return Err("Expected a [key, value] pair".to_string())
+                }
+                let value = pair.pop().unwrap();
+                let key = pair.pop().unwrap();
+                context.stack.push(value);
+                context.stack.push(key);
                 context.advance()
             },
             Op::toBool => {                
@@ -302,7 +382,7 @@ impl<'a> Runner<'a> {
             },
             Op::moveStackToHeapArray(op_param) => {                
                 let p = context.pop_stack()?;                                
-                self.state.pushToArray(*op_param as usize, p, vec![]);                
+                self.state.pushToArray(*op_param as usize, p, vec![])?;                
                 context.advance()
             },
             Op::arrayPush => {
@@ -356,9 +436,15 @@ impl<'a> Runner<'a> {
             Op::enforceSchemaInstanceOnHeap{heap_pos, schema} => {                
                 let v = self.state.get_var(*heap_pos as usize, vec![])?;
                 
-                context.stack.push(InterpreterType::bool(schema.adheres(&v, self.globals.schemas, self.globals.public_key)));
+                context.stack.push(InterpreterType::bool(schema.adheres(&v, self.globals.schemas, &self.globals.keys)));
                 context.advance()        
             },
+            Op::enforceSchemaInstanceOnStack(schema) => {
+                let v = context.stack.last().safe_unwrap()?;
+                let adheres = schema.adheres(v, self.globals.schemas, &self.globals.keys);
+                context.stack.push(InterpreterType::bool(adheres));
+                context.advance()
+            },
             Op::extractFields(op_param) => {                
                 let mut original_object = context.pop_stack()?.to_obj()?;
                 for selector in op_param {
@@ -468,12 +554,36 @@ impl<'a> Runner<'a> {
                 context.stack.push(result);
                 context.advance()
             },
+            Op::nNegate => {
+                let result = context.pop_stack()?.negate()?;
+                context.stack.push(result);
+                context.advance()
+            },
             Op::getKeys => {                
-                let mut obj = context.pop_stack()?.to_obj()?;
-                let keys = obj.drain().map(|(k, _v)| InterpreterType::string(k)).collect();
+                let obj = context.pop_stack()?.to_obj()?;
+                let keys = sorted_entries(obj).into_iter().map(|(k, _v)| InterpreterType::string(k)).collect();
                 context.stack.push(InterpreterType::Array(keys));
                 context.advance()        
             },
+            Op::toIterable{entries} => {
+                let mut items: Vec<InterpreterType> = match context.pop_stack()? {
+                    InterpreterType::Array(a) => if *entries {
+                        a.into_iter().enumerate().map(|(i, v)| InterpreterType::Array(vec![InterpreterType::int(i as i64), v])).collect()
+                    } else {
+                        a
+                    },
+                    InterpreterType::Object(o) => sorted_entries(o.0).into_iter().map(|(k, v)| if *entries {
+                        InterpreterType::Array(vec![InterpreterType::string(k), v])
+                    } else {
+                        InterpreterType::string(k)
+                    }).collect(),
+                    _ => return Err("Can only iterate over arrays and objects".to_string())
+                };
+                // Loops pop items off the back, so reverse them to visit in order.
+                items.reverse();
+                context.stack.push(InterpreterType::Array(items));
+                context.advance()
+            },
             Op::invoke{name, args} => {                
                 let args = context.stack.split_off(context.stack.len() - *args as usize);
                 let next_ops = self.globals.fns.get(name).safe_unwrap()?;
@@ -487,24 +597,144 @@ impl<'a> Runner<'a> {
                 context.advance()
                      
             },
+            Op::makeClosure{name, captures} => {
+                let captured = context.stack.split_off(context.stack.len() - *captures as usize);
+                context.stack.push(InterpreterType::Function(Closure {name: name.clone(), captured}));
+                context.advance()
+            },
+            Op::invokeValue{args} => {
+                let args = context.stack.split_off(context.stack.len() - *args as usize);
+                let function = context.pop_stack()?;
+                let res = self.call(&function, args)?;
+                context.stack.push(res);
+                context.advance()
+            },
+            Op::arrayMap => {
+                let function = context.pop_stack()?;
+                let mut mapped = vec![];
+                for elt in context.pop_stack()?.to_array()? {
+                    mapped.push(self.call(&function, vec![elt])?);
+                }
+                context.stack.push(InterpreterType::Array(mapped));
+                context.advance()
+            },
+            Op::arrayFilter => {
+                let function = context.pop_stack()?;
+                let mut kept = vec![];
+                for elt in context.pop_stack()?.to_array()? {
+                    if self.call(&function, vec![elt.clone()])?.to_bool()? {
+                        kept.push(elt);
+                    }
+                }
+                context.stack.push(InterpreterType::Array(kept));
+                context.advance()
+            },
+            op @ (Op::arrayFind | Op::arrayAny | Op::arrayAll) => {
+                let function = context.pop_stack()?;
+                // all looks for an element the function doesn't hold for.
+                let wanted = !matches!(op, Op::arrayAll);
+                let mut found = None;
+                for elt in context.pop_stack()?.to_array()? {
+                    if self.call(&function, vec![elt.clone()])?.to_bool()? == wanted {
+                        found = Some(elt);
+                        break;
+                    }
+                }
+                context.stack.push(match op {
+                    Op::arrayFind => found.unwrap_or(InterpreterType::None),
+                    Op::arrayAny => InterpreterType::bool(found.is_some()),
+                    _ => InterpreterType::bool(found.is_none())
+                });
+                context.advance()
+            },
+            Op::arrayReduce => {
+                let mut acc = context.pop_stack()?;
+                let function = context.pop_stack()?;
+                for elt in context.pop_stack()?.to_array()? {
+                    acc = self.call(&function, vec![acc, elt])?;
+                }
+                context.stack.push(acc);
+                context.advance()
+            },
+            Op::arraySort{comparator} => {
+                let function = if *comparator { Some(context.pop_stack()?) } else { None };
+                let array = context.pop_stack()?.to_array()?;
+                let array = merge_sort(array, &mut |a, b| match &function {
+                    Some(function) => self.call(function, vec![a.clone(), b.clone()])
+                        .and_then(|res| res.compare(&InterpreterType::int(0))),
+                    None => natural_order(a, b)
+                })?;
+                context.stack.push(InterpreterType::Array(array));
+                context.advance()
+            },
+            Op::arraySlice{end} => {
+                let end = if *end { Some(context.pop_stack()?.to_index()?) } else { None };
+                let start = context.pop_stack()?.to_index()?;
+                let array = context.pop_stack()?.to_array()?;
+                let end = end.unwrap_or(array.len()).min(array.len());
+                let start = start.min(end);
+                context.stack.push(InterpreterType::Array(array[start..end].to_vec()));
+                context.advance()
+            },
+            Op::arrayConcat => {
+                let mut other = context.pop_stack()?.to_array()?;
+                let mut array = context.pop_stack()?.to_array()?;
+                array.append(&mut other);
+                context.stack.push(InterpreterType::Array(array));
+                context.advance()
+            },
+            Op::arrayReverse => {
+                let mut array = context.pop_stack()?.to_array()?;
+                array.reverse();
+                context.stack.push(InterpreterType::Array(array));
+                context.advance()
+            },
+            Op::arrayIndexOf => {
+                let target = context.pop_stack()?;
+                let array = context.pop_stack()?.to_array()?;
+                let index = array.iter().position(|elt| elt.equals(&target)).map_or(-1, |i| i as i64);
+                context.stack.push(InterpreterType::int(index));
+                context.advance()
+            },
+            Op::arrayFlatten => {
+                let mut flat = vec![];
+                for elt in context.pop_stack()?.to_array()? {
+                    match elt {
+                        InterpreterType::Array(mut inner) => flat.append(&mut inner),
+                        other => flat.push(other)
+                    };
+                }
+                context.stack.push(InterpreterType::Array(flat));
+                context.advance()
+            },
             Op::signRole => {                
                 let mut obj = match context.pop_stack()? {
                     InterpreterType::Object(o) => o.0,
                     _ => return Err("Require an object for signing".to_string())
                 };
+                if obj.get("_state").map_or(false, |state| state.contains_function()) {
+                    return Err("Role instances cannot hold functions".to_string())
+                }
                 let name_value = obj.remove("_name").safe_unwrap()?.to_str()?;
-                
-                let mut hasher = DefaultHasher::new();
-                hasher.write(name_value.as_bytes());
-                match obj.get("_state") {
-                    Some(state) => state.hash(&mut hasher),
-                    _ => {}
+                let state_schema = match self.globals.schemas.get(&name_value) {
+                    Some(Schema::Role(_, state_schema)) => &state_schema[0],
+                    _ => return Err(format!("{} is not a role", name_value))
                 };
-                let msg: [u8; 8] = hasher.finish().to_be_bytes();
-                let sig: [u8; 64] = ed25519::signature(&msg, self.globals.private_key);
-                if !ed25519::verify(&msg, self.globals.public_key, &sig) {
+                let empty = InterpreterType::Object(Obj(HashMap::with_capacity(0)));
+                if !state_schema.adheres(obj.get("_state").unwrap_or(&empty), self.globals.schemas, &self.globals.keys) {
+                    return Err(format!("Data does not match role {}", name_value));
+                }
+
+                let keys = &self.globals.keys;
+                let claims = keys.claims();
+                let sig = roles::sign(&name_value, obj.get("_state"), &claims, &keys.private_key);
+                if !roles::verify(&name_value, obj.get("_state"), &claims, &sig, &keys.public_key) {
                     return Err(format!("Public key cannot validate signature."));
                 }
+                obj.insert("_v".to_string(), InterpreterType::int(roles::ROLE_VERSION));
+                obj.insert("_iat".to_string(), InterpreterType::int(claims.iat));
+                obj.insert("_exp".to_string(), InterpreterType::int(claims.exp));
+                obj.insert("_kid".to_string(), InterpreterType::string(claims.kid));
                 let all: Vec<InterpreterType> = sig.iter().map(|i| InterpreterType::int(*i as i64)).collect();
                 obj.insert("_sig".to_string(), InterpreterType::Array(all));
                 obj.insert("_name".to_string(), InterpreterType::string(name_value));
@@ -520,7 +750,8 @@ impl<'a> Runner<'a> {
                     InterpreterType::double(_) => "doub",
                     InterpreterType::Array(_) => "arr",
                     InterpreterType::string(_) => "str",
-                    InterpreterType::Object(_) => "obj"
+                    InterpreterType::Object(_) => "obj",
+                    InterpreterType::Function(_) => "func"
                 };
                 context.stack.push(InterpreterType::string(s.to_string()));
                 context.advance()
@@ -529,6 +760,28 @@ impl<'a> Runner<'a> {
         }
     }
 
+    /// Calls a closure with `args`, after the values it captured.
+    fn call(&mut self, function: &InterpreterType, mut args: Vec<InterpreterType>) -> Result<InterpreterType, String> {
+        let closure = match function {
+            InterpreterType::Function(closure) => closure,
+            _ => return Err("Only functions can be called".to_string())
+        };
+        let next_ops = self.globals.lambdas.get(&closure.name).or_else(|| self.globals.fns.get(&closure.name)).safe_unwrap()?;
+        if let Some(Op::assertHeapLen(expected)) = next_ops.first() {
+            let expected = (*expected as usize).checked_sub(closure.captured.len())
+                .ok_or_else(|| format!("Function {} captures more values than it takes", closure.name))?;
+            if expected != args.len() {
+                return Err(format!("Function takes {} argument(s) but {} were given", expected, args.len()))
+            }
+        }
+        let mut heap = closure.captured.clone();
+        heap.append(&mut args);
+        self.state.push(heap);
+        let res = self.run(Context::new(next_ops));
+        self.state.pop();
+        res
+    }
+
     pub fn run(&mut self, mut context: Context<'a>) -> Result<InterpreterType, String> {
         if context.exec.ops.len() == 0 {
             return Ok(InterpreterType::None)
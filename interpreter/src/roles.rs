@@ -0,0 +1,216 @@
+use crypto::digest::Digest;
+use crypto::ed25519;
+use crypto::sha2::Sha256;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::data::InterpreterType;
+
+/// Version of the role signing scheme, stored in each instance as `_v`.
+pub const ROLE_VERSION: i64 = 2;
+
+/// The first signing scheme, which had no claims. Its instances never expire,
+/// so they are rejected unless the host sets `Keyring::legacy_until`.
+pub const LEGACY_ROLE_VERSION: i64 = 1;
+
+const DOMAIN: &[u8] = b"tuna-role";
+
+/// Appends the canonical encoding of `value` to `out`.
+/// Every value starts with a tag byte, numbers are big-endian, strings, arrays
+/// and objects are length-prefixed, and object entries are ordered by key.
+/// Changing this encoding requires bumping `ROLE_VERSION`.
+pub fn encode(value: &InterpreterType, out: &mut Vec<u8>) {
+    match value {
+        InterpreterType::None => out.push(0),
+        InterpreterType::bool(b) => {
+            out.push(1);
+            out.push(*b as u8);
+        },
+        InterpreterType::int(i) => {
+            out.push(2);
+            out.extend_from_slice(&i.to_be_bytes());
+        },
+        InterpreterType::double(d) => {
+            out.push(3);
+            out.extend_from_slice(&d.to_bits().to_be_bytes());
+        },
+        InterpreterType::string(s) => {
+            out.push(4);
+            encode_str(s, out);
+        },
+        InterpreterType::Array(a) => {
+            out.push(5);
+            out.extend_from_slice(&(a.len() as u64).to_be_bytes());
+            for entry in a {
+                encode(entry, out);
+            }
+        },
+        InterpreterType::Object(o) => {
+            out.push(6);
+            out.extend_from_slice(&(o.0.len() as u64).to_be_bytes());
+            let mut keys: Vec<&String> = o.0.keys().collect();
+            keys.sort();
+            for k in keys {
+                encode_str(k, out);
+                encode(o.0.get(k).unwrap(), out);
+            }
+        },
+        // Never signed, but values claiming to be role instances may hold them.
+        InterpreterType::Function(f) => {
+            out.push(7);
+            encode_str(&f.name, out);
+        }
+    };
+}
+
+fn encode_str(s: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(s.len() as u64).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Issuance details signed along with a role instance.
+pub struct Claims {
+    pub iat: i64,
+    pub exp: i64,
+    pub kid: String
+}
+
+fn message(version: i64, name: &str, state: Option<&InterpreterType>) -> Vec<u8> {
+    let mut msg = DOMAIN.to_vec();
+    msg.extend_from_slice(&version.to_be_bytes());
+    encode_str(name, &mut msg);
+    match state {
+        Some(state) => {
+            msg.push(1);
+            encode(state, &mut msg);
+        },
+        None => msg.push(0)
+    };
+    msg
+}
+
+/// SHA-256 of the versioned message signed for a role instance.
+pub fn digest(name: &str, state: Option<&InterpreterType>, claims: &Claims) -> [u8; 32] {
+    let mut msg = message(ROLE_VERSION, name, state);
+    msg.extend_from_slice(&claims.iat.to_be_bytes());
+    msg.extend_from_slice(&claims.exp.to_be_bytes());
+    encode_str(&claims.kid, &mut msg);
+    hash(&msg)
+}
+
+/// The digest `LEGACY_ROLE_VERSION` instances were signed over.
+pub fn legacy_digest(name: &str, state: Option<&InterpreterType>) -> [u8; 32] {
+    hash(&message(LEGACY_ROLE_VERSION, name, state))
+}
+
+fn hash(msg: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input(msg);
+    let mut out = [0u8; 32];
+    hasher.result(&mut out);
+    out
+}
+
+pub fn sign(name: &str, state: Option<&InterpreterType>, claims: &Claims, private_key: &[u8]) -> [u8; 64] {
+    ed25519::signature(&digest(name, state, claims), private_key)
+}
+
+pub fn verify(name: &str, state: Option<&InterpreterType>, claims: &Claims, signature: &[u8], public_key: &[u8]) -> bool {
+    signature.len() == 64 && ed25519::verify(&digest(name, state, claims), public_key, signature)
+}
+
+pub fn legacy_verify(name: &str, state: Option<&InterpreterType>, signature: &[u8], public_key: &[u8]) -> bool {
+    signature.len() == 64 && ed25519::verify(&legacy_digest(name, state), public_key, signature)
+}
+
+/// Seconds since the unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Identifies a public key by the first 8 bytes of its SHA-256, in hex.
+pub fn key_id(public_key: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(public_key);
+    let mut out = [0u8; 32];
+    hasher.result(&mut out);
+    out[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub struct RetiredKey {
+    pub public_key: [u8; 32],
+    pub retired_at: i64
+}
+
+/// The key role instances are signed with, plus retired public keys that
+/// still verify instances issued before a rotation.
+pub struct Keyring {
+    pub kid: String,
+    pub private_key: [u8; 64],
+    pub public_key: [u8; 32],
+    pub retired: HashMap<String, RetiredKey>,
+    /// How long, in seconds, a signed role instance stays valid.
+    pub ttl: i64,
+    /// When, in seconds since the unix epoch, `LEGACY_ROLE_VERSION` instances
+    /// stop being accepted. They are rejected outright when this is `None`.
+    pub legacy_until: Option<i64>
+}
+
+pub const DEFAULT_TTL: i64 = 60 * 60 * 24;
+
+impl Keyring {
+    pub fn new(private_key: [u8; 64], public_key: [u8; 32]) -> Keyring {
+        Keyring {
+            kid: key_id(&public_key),
+            private_key,
+            public_key,
+            retired: HashMap::new(),
+            ttl: DEFAULT_TTL,
+            legacy_until: None
+        }
+    }
+
+    /// Finds the public key for `kid`, whether current or retired.
+    pub fn verifying_key(&self, kid: &str) -> Option<&[u8; 32]> {
+        if kid == self.kid {
+            return Some(&self.public_key)
+        }
+        self.retired.get(kid).map(|k| &k.public_key)
+    }
+
+    /// Every public key that may have signed a `LEGACY_ROLE_VERSION` instance,
+    /// which doesn't say which key signed it.
+    pub fn legacy_keys(&self) -> Vec<&[u8; 32]> {
+        match self.legacy_until {
+            Some(until) if now() < until => {},
+            _ => return vec![]
+        };
+        let mut keys = vec![&self.public_key];
+        keys.extend(self.retired.values().map(|k| &k.public_key));
+        keys
+    }
+
+    /// Signs with a new key from now on. The old public key keeps verifying
+    /// instances until every instance it could have signed has expired.
+    pub fn rotate(&mut self, private_key: [u8; 64], public_key: [u8; 32]) {
+        let now = now();
+        let ttl = self.ttl;
+        self.retired.retain(|_, k| k.retired_at + ttl > now);
+        let old_kid = std::mem::replace(&mut self.kid, key_id(&public_key));
+        self.retired.insert(old_kid, RetiredKey {
+            public_key: self.public_key,
+            retired_at: now
+        });
+        self.private_key = private_key;
+        self.public_key = public_key;
+    }
+
+    pub fn claims(&self) -> Claims {
+        let iat = now();
+        Claims {
+            iat,
+            exp: iat + self.ttl,
+            kid: self.kid.clone()
+        }
+    }
+}
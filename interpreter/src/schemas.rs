@@ -1,16 +1,14 @@
 use ts_rs::{TS, export};
 use std::collections::HashMap;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::any::TypeId;
 use std::convert::TryInto;
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
-use crypto::ed25519;
 
 use crate::data::{InterpreterType, Obj};
+use crate::roles::{self, Keyring};
 
-#[derive(Clone)]
-pub struct ObjSchema(HashMap<String, Schema>);
+#[derive(Clone, Debug)]
+pub struct ObjSchema(pub HashMap<String, Schema>);
 
 impl TS for  ObjSchema {
     fn name() -> String {
@@ -30,6 +28,12 @@ impl TS for  ObjSchema {
     }
 }
 
+impl Serialize for ObjSchema {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
 impl<'de> Deserialize<'de> for ObjSchema {
     fn deserialize<D>(deserializer: D) ->  Result<Self, D::Error> where D: Deserializer<'de>{
         let data = HashMap::deserialize(deserializer)?;
@@ -37,7 +41,7 @@ impl<'de> Deserialize<'de> for ObjSchema {
     }
 }
 
-#[derive(Deserialize, Clone, TS)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
 #[serde(tag = "kind", content= "data")]
 pub enum Schema {
     Object(ObjSchema),
@@ -51,7 +55,8 @@ pub enum Schema {
     string,
     bool,
     Any,
-    none
+    none,
+    Function
 }
 
 
@@ -72,15 +77,15 @@ impl Schema {
         }
     }
 
-    pub fn adheres(&self, value: &InterpreterType, schemas: &HashMap<String, Schema>, public_key: &[u8]) -> bool {
+    pub fn adheres(&self, value: &InterpreterType, schemas: &HashMap<String, Schema>, keys: &Keyring) -> bool {
         match self {
-            Schema::Union(options) => options.into_iter().any(|o| o.adheres(value, schemas, public_key)),
+            Schema::Union(options) => options.into_iter().any(|o| o.adheres(value, schemas, keys)),
             Schema::TypeAlias(name) => match schemas.get(name) {
-                Some(t) => t.adheres(value, schemas, public_key),
+                Some(t) => t.adheres(value, schemas, keys),
                 None => false
             },
             Schema::Map(entry_t) => match value {
-                InterpreterType::Object(internal_value) => internal_value.0.values().all(|v| entry_t[0].adheres(v, schemas, public_key)),
+                InterpreterType::Object(internal_value) => internal_value.0.values().all(|v| entry_t[0].adheres(v, schemas, keys)),
                 _ => false
             },
             Schema::Object(internal_schema) => match value {
@@ -89,7 +94,7 @@ impl Schema {
                     let mut adheres = true;
                     for (k, v_schema) in &internal_schema.0 {
                         adheres = match internal_value.0.get(k) {
-                            Some(v_value) => v_schema.adheres(v_value, schemas, public_key),
+                            Some(v_value) => v_schema.adheres(v_value, schemas, keys),
                             None => {
                                 if v_schema.is_optional() {
                                     optionals_missing += 1;
@@ -108,7 +113,7 @@ impl Schema {
                 _ => false
             },
             Schema::Array(internal) => match value {
-                InterpreterType::Array(internal_value) => internal_value.iter().all(|val| internal[0].adheres(&val, schemas, public_key)),
+                InterpreterType::Array(internal_value) => internal_value.iter().all(|val| internal[0].adheres(&val, schemas, keys)),
                 _ => false
             },
             Schema::none => match value {
@@ -116,7 +121,7 @@ impl Schema {
                 _ => false
             },
             
-            Schema::Role(_role_name, state_schema) => {
+            Schema::Role(role_name, state_schema) => {
                 let obj = match value {
                     InterpreterType::Object(o) => o,
                     _ => return false
@@ -128,6 +133,9 @@ impl Schema {
                     },
                     None => return false
                 };
+                if name != role_name {
+                    return false
+                }
                 let given_signature = match obj.0.get("_sig") {
                     Some(sig) => match sig {
                         InterpreterType::Array(a) => {
@@ -151,22 +159,32 @@ impl Schema {
                     },
                     None => return false
                 };
-                if given_signature.len() != 64 {
-                    return false
-                }
-                let mut hasher = DefaultHasher::new();
-                hasher.write(name.as_bytes());
-                let check_state = match obj.0.get("_state") {
-                    Some(state) => {
-                        state.hash(&mut hasher);
-                        Some(state)
+                let check_state = obj.0.get("_state");
+                let signed = match obj.0.get("_v") {
+                    Some(InterpreterType::int(v)) if *v == roles::ROLE_VERSION => {
+                        let claims = match (obj.0.get("_iat"), obj.0.get("_exp"), obj.0.get("_kid")) {
+                            (Some(InterpreterType::int(iat)), Some(InterpreterType::int(exp)), Some(InterpreterType::string(kid))) => roles::Claims {
+                                iat: *iat,
+                                exp: *exp,
+                                kid: kid.to_string()
+                            },
+                            _ => return false
+                        };
+                        if claims.exp <= roles::now() {
+                            return false
+                        }
+                        match keys.verifying_key(&claims.kid) {
+                            Some(public_key) => roles::verify(name, check_state, &claims, given_signature.as_slice(), public_key),
+                            None => false
+                        }
                     },
-                    None => None
+                    Some(InterpreterType::int(v)) if *v == roles::LEGACY_ROLE_VERSION => keys.legacy_keys().into_iter()
+                        .any(|public_key| roles::legacy_verify(name, check_state, given_signature.as_slice(), public_key)),
+                    _ => false
                 };
-                let msg: [u8; 8] = hasher.finish().to_be_bytes();
-                ed25519::verify(&msg, public_key, given_signature.as_slice()) && match check_state {
-                    Some(state) => state_schema[0].adheres(state, schemas, public_key),
-                    None => state_schema[0].adheres(&InterpreterType::Object(Obj(HashMap::with_capacity(0))), schemas, public_key)
+                signed && match check_state {
+                    Some(state) => state_schema[0].adheres(state, schemas, keys),
+                    None => state_schema[0].adheres(&InterpreterType::Object(Obj(HashMap::with_capacity(0))), schemas, keys)
                 }
             },
         Schema::Any => true,
@@ -186,6 +204,10 @@ impl Schema {
         Schema::bool => match value {
             InterpreterType::bool(_) => true,
             _ => false
+        },
+        Schema::Function => match value {
+            InterpreterType::Function(_) => true,
+            _ => false
         }
         }
     }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use crate::ops::Op;
+
+/// What is known about a frame before an op runs.
+#[derive(Clone, Copy, PartialEq)]
+struct Frame {
+    stack: usize,
+    heap: usize
+}
+
+/// How an op uses the stack: it needs `needs` values to be present, and
+/// leaves the stack `pops` values shorter and then `pushes` values longer.
+struct Effect {
+    needs: usize,
+    pops: usize,
+    pushes: usize
+}
+
+fn effect(op: &Op) -> Effect {
+    let (needs, pops, pushes) = match op {
+        Op::noop | Op::offsetOpCursor{..} | Op::truncateHeap(_) | Op::assertHeapLen(_) |
+        Op::returnVoid | Op::raiseError(_) => (0, 0, 0),
+        Op::instantiate(_) | Op::copyFromHeap(_) | Op::enforceSchemaOnHeap{..} |
+        Op::enforceSchemaInstanceOnHeap{..} => (0, 0, 1),
+        Op::isLastNone | Op::toBool | Op::enforceSchemaInstanceOnStack(_) => (1, 0, 1),
+        Op::negatePrev | Op::stackTopMatches{..} | Op::tryGetField(_) | Op::fieldAccess(_) |
+        Op::arrayLen | Op::repackageCollection | Op::nNegate | Op::getKeys |
+        Op::toIterable{..} | Op::getType | Op::signRole => (1, 1, 1),
+        Op::overwriteArg(_) | Op::conditonallySkipXops(_) | Op::moveStackTopToHeap |
+        Op::popStack | Op::moveStackToHeapArray(_) | Op::returnStackTop => (1, 1, 0),
+        Op::popArray | Op::ndArrayLen | Op::unpackPair => (1, 1, 2),
+        Op::fieldExists | Op::equal | Op::less | Op::lesseq | Op::boolAnd | Op::boolOr |
+        Op::plus | Op::nMinus | Op::nDivide | Op::nMult => (2, 2, 1),
+        Op::arrayPush | Op::assignPreviousToField(_) | Op::setNestedField(_) => (2, 1, 0),
+        Op::pArrayPush{stack_offset} => (*stack_offset as usize + 2, 1, 0),
+        Op::setField{field_depth} => (*field_depth as usize + 2, *field_depth as usize + 1, 0),
+        Op::setSavedField{field_depth, ..} | Op::pushSavedField{field_depth, ..} => {
+            (*field_depth as usize + 1, *field_depth as usize + 1, 0)
+        },
+        Op::getField{field_depth} => (*field_depth as usize + 1, *field_depth as usize + 1, 1),
+        Op::getSavedField(field_depth, _) => (*field_depth as usize, *field_depth as usize, 1),
+        Op::deleteSavedField{field_depth, ..} => (*field_depth as usize, *field_depth as usize, 0),
+        Op::stringConcat{nStrings, ..} => (*nStrings as usize, *nStrings as usize, 1),
+        Op::extractFields(selectors) => (1, 1, selectors.len()),
+        Op::invoke{args, ..} => (*args as usize, *args as usize, 1),
+        Op::makeClosure{captures, ..} => (*captures as usize, *captures as usize, 1),
+        Op::invokeValue{args} => (*args as usize + 1, *args as usize + 1, 1),
+        Op::arrayReverse | Op::arrayFlatten => (1, 1, 1),
+        Op::arrayMap | Op::arrayFilter | Op::arrayFind | Op::arrayAny | Op::arrayAll |
+        Op::arrayConcat | Op::arrayIndexOf => (2, 2, 1),
+        Op::arrayReduce => (3, 3, 1),
+        Op::arraySort{comparator} => (1 + *comparator as usize, 1 + *comparator as usize, 1),
+        Op::arraySlice{end} => (2 + *end as usize, 2 + *end as usize, 1)
+    };
+    Effect {needs, pops, pushes}
+}
+
+/// The heap slot an op reads or writes, relative to the current frame.
+pub fn heap_slot(op: &Op) -> Option<usize> {
+    let slot = match op {
+        Op::overwriteArg(slot) | Op::copyFromHeap(slot) | Op::getSavedField(_, slot) |
+        Op::moveStackToHeapArray(slot) => slot,
+        Op::setSavedField{index, ..} | Op::deleteSavedField{index, ..} | Op::pushSavedField{index, ..} => index,
+        Op::enforceSchemaOnHeap{heap_pos, ..} | Op::enforceSchemaInstanceOnHeap{heap_pos, ..} => heap_pos,
+        _ => return None
+    };
+    Some(*slot as usize)
+}
+
+/// The index a jump at `index` continues from when taken, or `None` if `op`
+/// isn't a jump or would jump before the first op.
+pub fn jump_target(index: usize, op: &Op) -> Option<usize> {
+    match op {
+        Op::offsetOpCursor{offset, fwd: true} => Some(index + *offset as usize + 1),
+        Op::offsetOpCursor{offset, fwd: false} => index.checked_sub(*offset as usize),
+        Op::conditonallySkipXops(skip) => Some(index + *skip as usize + 1),
+        _ => None
+    }
+}
+
+/// The number of arguments a function checks for on entry, if it does.
+fn arity(ops: &[Op]) -> Option<usize> {
+    match ops.first() {
+        Some(Op::assertHeapLen(n)) => Some(*n as usize),
+        _ => None
+    }
+}
+
+/// Checks every function so the interpreter can't panic or index out of
+/// bounds running it: jumps land inside the function, the stack never
+/// underflows and has the same depth on every path into an op, heap slots
+/// exist when they're used, and invoked functions exist and take as many
+/// arguments as they're given. Lambdas can only be reached through closures.
+pub fn verify(fns: &HashMap<String, Vec<Op>>, lambdas: &HashMap<String, Vec<Op>>) -> Result<(), String> {
+    let mut names: Vec<(&String, &Vec<Op>)> = fns.iter().chain(lambdas.iter()).collect();
+    names.sort_by_key(|(name, _)| *name);
+    for (name, ops) in names {
+        verify_function(name, ops, fns, lambdas)?;
+    }
+    Ok(())
+}
+
+pub fn verify_function(name: &str, ops: &[Op], fns: &HashMap<String, Vec<Op>>, lambdas: &HashMap<String, Vec<Op>>) -> Result<(), String> {
+    let mut frames: Vec<Option<Frame>> = vec![None; ops.len()];
+    let mut pending = vec![];
+    if !ops.is_empty() {
+        frames[0] = Some(Frame {stack: 0, heap: arity(ops).unwrap_or(0)});
+        pending.push(0);
+    }
+
+    while let Some(index) = pending.pop() {
+        let op = &ops[index];
+        let at = |message: String| format!("{} op {} ({}): {}", name, index, kind(op), message);
+        let mut frame = frames[index].unwrap();
+
+        let effect = effect(op);
+        if frame.stack < effect.needs {
+            return Err(at(format!("needs {} value(s) on the stack but there are only {}", effect.needs, frame.stack)))
+        }
+        frame.stack = frame.stack - effect.pops + effect.pushes;
+        if let Some(slot) = heap_slot(op) {
+            if slot >= frame.heap {
+                return Err(at(format!("uses heap slot {} but only {} are in scope", slot, frame.heap)))
+            }
+        }
+        match op {
+            Op::moveStackTopToHeap => frame.heap += 1,
+            Op::truncateHeap(n) if *n > frame.heap => {
+                return Err(at(format!("drops {} heap slot(s) but only {} are in scope", n, frame.heap)))
+            },
+            Op::truncateHeap(n) => frame.heap -= n,
+            Op::assertHeapLen(n) if *n as usize != frame.heap => {
+                return Err(at(format!("expects {} heap slot(s) but {} are in scope", n, frame.heap)))
+            },
+            Op::invoke{name: callee, args} => match fns.get(callee).map(|ops| arity(ops)) {
+                None => return Err(at(format!("invokes unknown function {}", callee))),
+                Some(Some(expected)) if expected != *args as usize => {
+                    return Err(at(format!("passes {} argument(s) to {} which takes {}", args, callee, expected)))
+                },
+                Some(_) => {}
+            },
+            Op::makeClosure{name: callee, captures} => match lambdas.get(callee).or_else(|| fns.get(callee)).map(|ops| arity(ops)) {
+                None => return Err(at(format!("makes a closure of unknown function {}", callee))),
+                Some(Some(expected)) if expected < *captures as usize => {
+                    return Err(at(format!("captures {} value(s) for {} which takes {}", captures, callee, expected)))
+                },
+                Some(_) => {}
+            },
+            _ => {}
+        };
+
+        let mut next = vec![];
+        match op {
+            Op::returnStackTop | Op::returnVoid | Op::raiseError(_) => {},
+            Op::offsetOpCursor{..} => next.push(jump_target(index, op)),
+            Op::conditonallySkipXops(_) => next.extend(vec![Some(index + 1), jump_target(index, op)]),
+            _ => next.push(Some(index + 1))
+        };
+        for target in next {
+            let target = match target {
+                Some(target) if target <= ops.len() => target,
+                _ => return Err(at("jumps outside the function".to_string()))
+            };
+            // Running off the end returns none.
+            if target == ops.len() {
+                continue;
+            }
+            match frames[target] {
+                None => {
+                    frames[target] = Some(frame);
+                    pending.push(target);
+                },
+                Some(seen) if seen != frame => {
+                    return Err(at(format!(
+                        "reaches op {} with {} stack value(s) and {} heap slot(s), but another path has {} and {}",
+                        target, frame.stack, frame.heap, seen.stack, seen.heap)))
+                },
+                Some(_) => {}
+            };
+        }
+    }
+    Ok(())
+}
+
+fn kind(op: &Op) -> String {
+    let debug = format!("{:?}", op);
+    debug[..debug.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(debug.len())].to_string()
+}
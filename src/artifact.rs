@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use crate::Compiled;
+
+/// Version of the artifact format. Bump it whenever `Compiled`, `Op` or
+/// `Schema` change shape, so stale artifacts are rejected instead of misread.
+pub const ARTIFACT_VERSION: u32 = 5;
+
+/// Binary artifacts start with these bytes, then the version as a big-endian
+/// u32, then the CBOR encoding of `Compiled`.
+const MAGIC: &[u8; 4] = b"TUNA";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Json,
+    Binary
+}
+
+#[derive(Serialize)]
+struct Artifact<'a> {
+    version: u32,
+    compiled: &'a Compiled
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32
+}
+
+#[derive(Deserialize)]
+struct Loaded {
+    compiled: Compiled
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version != ARTIFACT_VERSION {
+        return Err(format!("Artifact has version {} but this build reads version {}", version, ARTIFACT_VERSION))
+    }
+    Ok(())
+}
+
+impl Compiled {
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(&Artifact {version: ARTIFACT_VERSION, compiled: self})
+            .map_err(|e| format!("Could not encode artifact: {}", e))
+    }
+
+    pub fn from_json(json: &str) -> Result<Compiled, String> {
+        let header: Header = serde_json::from_str(json).map_err(|e| format!("Not a tuna artifact: {}", e))?;
+        check_version(header.version)?;
+        let loaded: Loaded = serde_json::from_str(json).map_err(|e| format!("Could not decode artifact: {}", e))?;
+        Ok(loaded.compiled)
+    }
+
+    pub fn to_binary(&self) -> Result<Vec<u8>, String> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&ARTIFACT_VERSION.to_be_bytes());
+        serde_cbor::to_writer(&mut out, self).map_err(|e| format!("Could not encode artifact: {}", e))?;
+        Ok(out)
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<Compiled, String> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err("Not a tuna artifact".to_string())
+        }
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&bytes[4..8]);
+        check_version(u32::from_be_bytes(version))?;
+        serde_cbor::from_slice(&bytes[8..]).map_err(|e| format!("Could not decode artifact: {}", e))
+    }
+
+    pub fn save(&self, path: &Path, format: Format) -> Result<(), String> {
+        let bytes = match format {
+            Format::Json => self.to_json()?.into_bytes(),
+            Format::Binary => self.to_binary()?
+        };
+        fs::write(path, bytes).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    /// Reads an artifact written by `save` in either format.
+    pub fn load(path: &Path) -> Result<Compiled, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        if bytes.starts_with(MAGIC) {
+            return Compiled::from_binary(&bytes)
+        }
+        let json = String::from_utf8(bytes).map_err(|_| "Not a tuna artifact".to_string())?;
+        Compiled::from_json(&json)
+    }
+}
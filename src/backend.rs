@@ -4,39 +4,207 @@ use tuna_interpreter::schemas::{Schema};
 use std::collections::HashMap;
 use crate::scope::{ScopeSizer};
 use crate::ir::*;
+use crate::optimizer;
+use crate::captures;
+use crate::error::{CompileError, ErrorCode};
 
+/// A jump target, placed in code with `Instr::Label`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct Label(pub(crate) usize);
 
+/// Ops plus symbolic jumps, which `link` turns into relative offsets once
+/// the final position of every op is known.
+#[derive(Clone, Debug)]
+pub(crate) enum Instr {
+    Op(Op),
+    Label(Label),
+    Jump(Label),
+    /// Pops a bool and jumps if it's true.
+    JumpIf(Label)
+}
 
-pub fn to_ops(function: Function) -> Vec<Op> {
-    let mut instrs = vec![];
-    instrs.push(Op::assertHeapLen(function.args.len() as u64));
-    let mut scope = ScopeSizer::new();
-    let mut heap_pos = 0;
-    for (schema, name) in function.args {
-        
-        scope.add(name.clone());
-        instrs.append(&mut vec![
-            Op::enforceSchemaInstanceOnHeap{schema, heap_pos},
-            Op::conditonallySkipXops(1),
-            Op::raiseError(format!("Input did not match expectations for {}", name))
-        ]);
-        heap_pos += 1;
+impl From<Op> for Instr {
+    fn from(op: Op) -> Instr {
+        Instr::Op(op)
     }
-    for b in function.body {
-        instrs.append(&mut match b {
-            Either::Left(l) => l.to_ops(&mut scope),
-            Either::Right(r) => (*r).to_ops(&mut scope)
+}
+
+#[derive(Default)]
+pub(crate) struct Code(pub(crate) Vec<Instr>);
+
+impl Code {
+    fn push(&mut self, instr: impl Into<Instr>) {
+        self.0.push(instr.into());
+    }
+
+    fn append(&mut self, other: &mut Code) {
+        self.0.append(&mut other.0);
+    }
+
+    fn extend(&mut self, ops: Vec<Op>) {
+        self.0.extend(ops.into_iter().map(Instr::Op));
+    }
+}
+
+/// Replaces labels and jumps with ops that offset the cursor.
+pub(crate) fn link(code: Vec<Instr>) -> Vec<Op> {
+    let mut positions = HashMap::new();
+    let mut index = 0;
+    for instr in &code {
+        match instr {
+            Instr::Label(label) => {
+                positions.insert(*label, index);
+            },
+            _ => index += 1
+        };
+    }
+    let mut ops = Vec::with_capacity(index);
+    for instr in code {
+        let here = ops.len();
+        ops.push(match instr {
+            Instr::Op(op) => op,
+            Instr::Label(_) => continue,
+            Instr::Jump(label) => match positions[&label] {
+                target if target > here => Op::offsetOpCursor{offset: (target - here - 1) as u64, fwd: true},
+                target => Op::offsetOpCursor{offset: (here - target) as u64, fwd: false}
+            },
+            Instr::JumpIf(label) => match positions[&label] {
+                target if target > here => Op::conditonallySkipXops((target - here - 1) as u64),
+                _ => panic!("Conditional jumps can only go forward")
+            }
         });
     }
-    instrs
+    ops
+}
+
+pub fn to_ops(function: Function) -> Result<Vec<Op>, CompileError> {
+    lower(function).map(|lowered| lowered.ops)
+}
+
+pub struct LoweredFunction {
+    pub ops: Vec<Op>,
+    /// The names that occupied each heap slot.
+    pub slots: Vec<Vec<String>>,
+    /// The lambdas defined in the function, which are functions of their own.
+    pub lambdas: Vec<(String, Vec<Op>)>
+}
+
+/// What lowering a function tracks besides its heap slots.
+struct FunctionScope {
+    heap: ScopeSizer,
+    /// The declared return schema of the function being lowered.
+    returns: Schema,
+    labels: usize,
+    /// Where `continue` and `break` jump to in each enclosing loop, and how
+    /// many heap slots were in use when the loop started.
+    loops: Vec<(Label, Label, usize)>,
+    /// The function being lowered, which its lambdas are named after.
+    name: String,
+    /// Lambdas lowered so far, along with the lambdas inside them.
+    lambdas: Vec<(String, Vec<Op>)>,
+    lambda_count: usize,
+    /// Variables written to that have no heap slot, which the resolver should have reported.
+    unresolved: Vec<String>
+}
+
+impl FunctionScope {
+    fn new(name: String, returns: Schema, heap: ScopeSizer) -> FunctionScope {
+        FunctionScope {
+            heap,
+            returns,
+            labels: 0,
+            loops: vec![],
+            name,
+            lambdas: vec![],
+            lambda_count: 0,
+            unresolved: vec![]
+        }
+    }
+
+    /// The scope of a lambda defined here, whose first heap slots hold `captures`.
+    fn nested(&self, captures: &[String]) -> FunctionScope {
+        let mut heap = ScopeSizer::new();
+        for name in captures {
+            heap.add(name.clone());
+        }
+        FunctionScope::new(format!("{}#{}", self.name, self.lambda_count), Schema::Any, heap)
+    }
+
+    /// Moves on to naming the next lambda once one has been lowered.
+    fn defined(&mut self, mut lambda: FunctionScope, ops: Vec<Op>) {
+        self.lambda_count += 1;
+        self.unresolved.append(&mut lambda.unresolved);
+        self.lambdas.append(&mut lambda.lambdas);
+        self.lambdas.push((lambda.name, ops));
+    }
+
+    /// A jump target no other code in the function uses.
+    fn label(&mut self) -> Label {
+        self.labels += 1;
+        Label(self.labels)
+    }
+
+    /// Starts a loop whose body may jump to `next` or `end`, which both expect
+    /// the heap as it is now.
+    fn enter_loop(&mut self, next: Label, end: Label) {
+        let size = self.heap.size();
+        self.loops.push((next, end, size));
+    }
+
+    fn exit_loop(&mut self) {
+        self.loops.pop();
+    }
+
+    /// Where a `break` or `continue` jumps to, and how many heap slots it must
+    /// drop first.
+    fn loop_exit(&self, breaking: bool) -> (Label, usize) {
+        let (next, end, size) = *self.loops.last().unwrap();
+        (if breaking { end } else { next }, self.heap.size() - size)
+    }
+}
+
+pub fn lower(function: Function) -> Result<LoweredFunction, CompileError> {
+    let mut scope = FunctionScope::new(function.name.to_string(), function.returns, ScopeSizer::new());
+    let body: Vec<Root> = function.body.into_iter().map(|b| match b {
+        Either::Left(l) => l,
+        Either::Right(r) => Root::Discard(r)
+    }).collect();
+    let ops = function_ops(&mut scope, &function.args, &body);
+    if let Some(name) = scope.unresolved.first() {
+        return Err(CompileError::new(ErrorCode::UndefinedName, format!("Cannot assign to {}, which is not a variable", name), function.span))
+    }
+    Ok(LoweredFunction {ops, slots: scope.heap.slots, lambdas: scope.lambdas})
+}
+
+/// Checks the arguments and runs the body. Heap slots already in `scope`
+/// hold captured values, which are passed ahead of the arguments.
+fn function_ops(scope: &mut FunctionScope, args: &[(Schema, String)], body: &[Root]) -> Vec<Op> {
+    let mut instrs = Code::default();
+    instrs.push(Op::assertHeapLen((scope.heap.size() + args.len()) as u64));
+    for (schema, name) in args {
+        let heap_pos = scope.heap.add(name.clone()) as u64;
+        let valid = scope.label();
+        instrs.push(Op::enforceSchemaInstanceOnHeap{schema: schema.clone(), heap_pos});
+        instrs.push(Instr::JumpIf(valid));
+        instrs.push(Op::raiseError(format!("Input did not match expectations for {}", name)));
+        instrs.push(Instr::Label(valid));
+    }
+    for b in body {
+        instrs.append(&mut b.to_ops(scope));
+    }
+    // Falling off the end returns none, which must match the declared type too.
+    if !matches!(scope.returns, Schema::Any) {
+        instrs.append(&mut Root::Return(None).to_ops(scope));
+    }
+    link(optimizer::optimize(instrs.0))
 }
 
 trait Compilable {
-    fn to_ops(&self, scope: &mut ScopeSizer) -> Vec<Op>;
+    fn to_ops(&self, scope: &mut FunctionScope) -> Code;
 }
 
 impl Compilable for Either<Root, AnyValue> {
-    fn to_ops(&self, scope: &mut ScopeSizer) -> Vec<Op> {
+    fn to_ops(&self, scope: &mut FunctionScope) -> Code {
         match self {
             Either::Left(l) => l.to_ops(scope),
             Either::Right(r) => r.to_ops(scope)
@@ -46,8 +214,8 @@ impl Compilable for Either<Root, AnyValue> {
 
 type Data = InterpreterType;
 impl Compilable for AnyValue {
-    fn to_ops(&self, scope: &mut ScopeSizer) -> Vec<Op> {
-        let mut instrs: Vec<Op> = vec![];
+    fn to_ops(&self, scope: &mut FunctionScope) -> Code {
+        let mut instrs = Code::default();
 
         match self {
             AnyValue::String(s) => instrs.push(Op::instantiate(Data::string(s.to_string()))),
@@ -71,10 +239,32 @@ impl Compilable for AnyValue {
                 instrs.append(&mut v.to_ops(scope));
                 instrs.push(Op::negatePrev);
             },
+            AnyValue::Negate(v) => {
+                instrs.append(&mut v.to_ops(scope));
+                instrs.push(Op::nNegate);
+            },
+            AnyValue::BinaryOp{sign: sign @ (Sign::And | Sign::Or), left, right} => {
+                // The right side only runs when the left doesn't settle the result.
+                // It's still combined with a literal so it must be a bool too.
+                let (short, end) = (scope.label(), scope.label());
+                let and = matches!(sign, Sign::And);
+                instrs.append(&mut left.to_ops(scope));
+                if and {
+                    instrs.push(Op::negatePrev);
+                }
+                instrs.push(Instr::JumpIf(short));
+                instrs.append(&mut right.to_ops(scope));
+                instrs.push(Op::instantiate(Data::bool(and)));
+                instrs.push(if and { Op::boolAnd } else { Op::boolOr });
+                instrs.push(Instr::Jump(end));
+                instrs.push(Instr::Label(short));
+                instrs.push(Op::instantiate(Data::bool(!and)));
+                instrs.push(Instr::Label(end));
+            },
             AnyValue::BinaryOp{sign, left, right} => {
                 instrs.append(&mut left.to_ops(scope));
                 instrs.append(&mut right.to_ops(scope));
-                instrs.append(&mut match sign {
+                instrs.extend(match sign {
                     Sign::Eq => vec![Op::equal],
                     Sign::Neq => vec![Op::equal, Op::negatePrev],
                     Sign::L => vec![Op::less],
@@ -83,8 +273,7 @@ impl Compilable for AnyValue {
                     Sign::Geq => vec![Op::less, Op::negatePrev],
                     Sign::Plus => vec![Op::plus],
                     Sign::Minus => vec![Op::nMinus],
-                    Sign::And => vec![Op::boolAnd],
-                    Sign::Or => vec![Op::boolOr],
+                    Sign::And | Sign::Or => unreachable!(),
                     Sign::Div => vec![Op::nDivide],
                     Sign::Mult => vec![Op::nMult]
                 });
@@ -93,11 +282,7 @@ impl Compilable for AnyValue {
                 instrs.append(&mut val.to_ops(scope));
                 instrs.push(Op::stackTopMatches{schema: typ.clone()});
             },
-            AnyValue::RoleInstance{schema, data} => {
-                let (name, _schem) = match schema {
-                    Schema::Role(name, schem) => (name, schem),
-                    _ => panic!("Unexpected schema")
-                };
+            AnyValue::RoleInstance{name, data} => {
                 let mut base_obj = HashMap::new();
                 base_obj.insert("_name".to_string(), Data::string(name.to_string()));
                 let object = Data::Object(Obj(base_obj));
@@ -114,7 +299,24 @@ impl Compilable for AnyValue {
                 }
                 instrs.push(Op::signRole); 
             },
-            AnyValue::Saved(name) => instrs.push(Op::copyFromHeap(scope.get(name))),
+            AnyValue::Saved(name) => instrs.push(match scope.heap.get(name) {
+                Some(index) => Op::copyFromHeap(index),
+                // Any other name is a function, used as a value.
+                None => Op::makeClosure{name: name.clone(), captures: 0}
+            }),
+            AnyValue::Lambda{args, body} => {
+                // The variables the lambda captures come first in its heap.
+                let (captures, slots): (Vec<String>, Vec<u64>) = captures::free_names(args, body).into_iter()
+                    .filter_map(|name| scope.heap.get(&name).map(|slot| (name, slot)))
+                    .unzip();
+                let mut lambda = scope.nested(&captures);
+                let ops = function_ops(&mut lambda, args, body);
+                for slot in slots {
+                    instrs.push(Op::copyFromHeap(slot));
+                }
+                instrs.push(Op::makeClosure{name: lambda.name.clone(), captures: captures.len() as u64});
+                scope.defined(lambda, ops);
+            },
             AnyValue::Selection{root, level} => {
                 instrs.append(&mut root.to_ops(scope));
                 if level.len() > 0 {
@@ -128,6 +330,35 @@ impl Compilable for AnyValue {
                 instrs.append(&mut v.to_ops(scope));
                 instrs.push(Op::getKeys);
             },
+            AnyValue::Len(v) => {
+                instrs.append(&mut v.to_ops(scope));
+                instrs.push(Op::arrayLen);
+            },
+            AnyValue::Has{val, key} => {
+                instrs.append(&mut val.to_ops(scope));
+                instrs.append(&mut key.to_ops(scope));
+                instrs.push(Op::fieldExists);
+            },
+            AnyValue::ArrayMethod{method, target, args} => {
+                instrs.append(&mut target.to_ops(scope));
+                for arg in args {
+                    instrs.append(&mut arg.to_ops(scope));
+                }
+                instrs.push(match method {
+                    ArrayMethod::Map => Op::arrayMap,
+                    ArrayMethod::Filter => Op::arrayFilter,
+                    ArrayMethod::Reduce => Op::arrayReduce,
+                    ArrayMethod::Sort => Op::arraySort{comparator: args.len() == 1},
+                    ArrayMethod::Find => Op::arrayFind,
+                    ArrayMethod::Any => Op::arrayAny,
+                    ArrayMethod::All => Op::arrayAll,
+                    ArrayMethod::Slice => Op::arraySlice{end: args.len() == 2},
+                    ArrayMethod::Concat => Op::arrayConcat,
+                    ArrayMethod::Reverse => Op::arrayReverse,
+                    ArrayMethod::IndexOf => Op::arrayIndexOf,
+                    ArrayMethod::Flatten => Op::arrayFlatten
+                });
+            },
             AnyValue::Array(vals) => {
                 instrs.push(Op::instantiate(Data::Array(vec![])));
                 for v in vals {
@@ -142,18 +373,24 @@ impl Compilable for AnyValue {
 }
 
 impl Compilable for Root {
-    fn to_ops(&self, scope: &mut ScopeSizer) -> Vec<Op> {
-        let mut instrs = vec![];
+    fn to_ops(&self, scope: &mut FunctionScope) -> Code {
+        let mut instrs = Code::default();
         
         match self {
             Root::Save{val, name} => {
-                scope.add(name.to_string());
+                scope.heap.add(name.to_string());
                 instrs.append(&mut val.to_ops(scope));
                 instrs.push(Op::moveStackTopToHeap);
             },
             Root::Update{root, level, operation} => {
                 let field_depth = level.len() as u64;
-                let index = scope.get(&root.0);
+                let index = match scope.heap.get(&root.0) {
+                    Some(index) => index,
+                    None => {
+                        scope.unresolved.push(root.0.clone());
+                        return instrs
+                    }
+                };
                 match operation {
                     Mut::Overwrite(val) => {
                         if field_depth == 0 {
@@ -168,16 +405,31 @@ impl Compilable for Root {
                         }
                     },
                     Mut::Push(vals) => {
-                        if field_depth > 0 {
-                            for l in level {
+                        if field_depth > 0 && vals.len() > 1 {
+                            // The path is evaluated once into hidden slots rather than once per value.
+                            scope.heap.push();
+                            let mut path = vec![];
+                            for (i, l) in level.iter().enumerate() {
+                                path.push(scope.heap.add(format!("#path{}", i)) as u64);
                                 instrs.append(&mut l.to_ops(scope));
+                                instrs.push(Op::moveStackTopToHeap);
+                            }
+                            for v in vals {
+                                for slot in &path {
+                                    instrs.push(Op::copyFromHeap(*slot));
+                                }
+                                instrs.append(&mut v.to_ops(scope));
+                                instrs.push(Op::pushSavedField{field_depth, index});
                             }
-                            instrs.push(Op::instantiate(Data::Array(vec![])));
+                            instrs.push(Op::truncateHeap(scope.heap.pop() as usize));
+                        } else if field_depth > 0 {
                             for v in vals {
+                                for l in level {
+                                    instrs.append(&mut l.to_ops(scope));
+                                }
                                 instrs.append(&mut v.to_ops(scope));
-                                instrs.push(Op::arrayPush);
+                                instrs.push(Op::pushSavedField{field_depth, index});
                             }
-                            instrs.push(Op::pushSavedField{field_depth, index});
                         } else {
                             for v in vals {
                                 instrs.append(&mut v.to_ops(scope));
@@ -193,57 +445,100 @@ impl Compilable for Root {
                     }
                 }
             },
-            Root::ForEach {target, body, arg} => {
-                scope.push();
-                scope.add(arg.to_string());
-                let mut loopbody = vec![
-                    Op::popArray,
-                    Op::moveStackTopToHeap,
-                ];
-                for b in body {
-                    loopbody.append(&mut b.to_ops(scope));
-                }
-                loopbody.push(Op::truncateHeap(scope.pop() as usize));
-                let loop_len = loopbody.len() as u64;
-                loopbody.push(Op::offsetOpCursor{offset: loop_len + 4, fwd: false});
-                
+            Root::ForEach {target, body, arg, value} => {
+                let (top, end) = (scope.label(), scope.label());
+                scope.enter_loop(top, end);
                 instrs.append(&mut target.to_ops(scope));
-                instrs.append(&mut vec![
+                instrs.push(Op::toIterable{entries: value.is_some()});
+                instrs.push(Instr::Label(top));
+                instrs.extend(vec![
                     Op::ndArrayLen,
                     Op::instantiate(Data::int(0)),
-                    Op::equal,
-                    Op::conditonallySkipXops(loop_len),
+                    Op::equal
                 ]);
-                instrs.append(&mut loopbody);
+                instrs.push(Instr::JumpIf(end));
+                instrs.push(Op::popArray);
+                scope.heap.push();
+                scope.heap.add(arg.to_string());
+                match value {
+                    Some(value) => {
+                        // Entries are [key, value] pairs; unpacking leaves the key on top.
+                        scope.heap.add(value.to_string());
+                        instrs.extend(vec![
+                            Op::unpackPair,
+                            Op::moveStackTopToHeap,
+                            Op::moveStackTopToHeap
+                        ]);
+                    },
+                    None => instrs.push(Op::moveStackTopToHeap)
+                };
+                for b in body {
+                    instrs.append(&mut b.to_ops(scope));
+                }
+                instrs.push(Op::truncateHeap(scope.heap.pop() as usize));
+                instrs.push(Instr::Jump(top));
+                instrs.push(Instr::Label(end));
                 instrs.push(Op::popStack);
+                scope.exit_loop();
             },
-            Root::Call(call) => instrs.append(&mut call.to_ops(scope)),
-            Root::Return(maybe_v) => match maybe_v {
-                Some(v) => {
-                    instrs.append(&mut v.to_ops(scope));
+            Root::While{condition, body} => {
+                let (top, end) = (scope.label(), scope.label());
+                scope.enter_loop(top, end);
+                instrs.push(Instr::Label(top));
+                instrs.append(&mut condition.to_ops(scope));
+                instrs.push(Op::negatePrev);
+                instrs.push(Instr::JumpIf(end));
+                scope.heap.push();
+                for b in body {
+                    instrs.append(&mut b.to_ops(scope));
+                }
+                let scope_size = scope.heap.pop();
+                if scope_size > 0 {
+                    instrs.push(Op::truncateHeap(scope_size as usize));
+                }
+                instrs.push(Instr::Jump(top));
+                instrs.push(Instr::Label(end));
+                scope.exit_loop();
+            },
+            Root::Break | Root::Continue => {
+                let (target, drop) = scope.loop_exit(matches!(self, Root::Break));
+                if drop > 0 {
+                    instrs.push(Op::truncateHeap(drop));
+                }
+                instrs.push(Instr::Jump(target));
+            },
+            Root::Call(call) => {
+                instrs.append(&mut call.to_ops(scope));
+                instrs.push(Op::popStack);
+            },
+            Root::Discard(v) => {
+                instrs.append(&mut v.to_ops(scope));
+                instrs.push(Op::popStack);
+            },
+            Root::Return(maybe_v) => match (maybe_v, &scope.returns) {
+                (None, Schema::Any) => instrs.push(Op::returnVoid),
+                (maybe_v, returns) => {
+                    let returns = returns.clone();
+                    match maybe_v {
+                        Some(v) => instrs.append(&mut v.to_ops(scope)),
+                        None => instrs.push(Op::instantiate(Data::None))
+                    };
+                    if !matches!(returns, Schema::Any) {
+                        let valid = scope.label();
+                        instrs.push(Op::enforceSchemaInstanceOnStack(returns));
+                        instrs.push(Instr::JumpIf(valid));
+                        instrs.push(Op::raiseError("Return value did not match expectations".to_string()));
+                        instrs.push(Instr::Label(valid));
+                    }
                     instrs.push(Op::returnStackTop);
-                },
-                None => instrs.push(Op::returnVoid)
+                }
             },
             Root::Branch(conds) => {
-                let mut branches = vec![];
-                let mut total_size = 0;
-                for c in conds {
-                    let this_one = c.to_ops(scope);
-                    total_size += this_one.len();
-                    branches.push(this_one);
-                }
-
-                let mut ops_before = 0;
-                branches.reverse();
-                while let Some(mut branch) = branches.pop() {
-                    // [(Ops before)(this branch)(x branch remaining, skip to end)(last branch) end of branch]
-                    let ops_remaining = total_size - ops_before - branch.len() + branches.len() - 1;
-                    ops_before += branch.len();
-                    branch.push(Op::offsetOpCursor{offset: ops_remaining as u64, fwd: true});
-                    instrs.append(&mut branch);
+                let end = scope.label();
+                for cond in conds {
+                    instrs.append(&mut cond.to_ops(scope, end));
                 }
-                instrs.push(Op::noop);
+                instrs.push(Instr::Label(end));
             }
         };
         instrs
@@ -251,35 +546,40 @@ impl Compilable for Root {
 }
 
 impl Compilable for Call {
-    fn to_ops(&self, scope: &mut ScopeSizer) -> Vec<Op> {
-        let mut instrs = vec![];
+    fn to_ops(&self, scope: &mut FunctionScope) -> Code {
+        let mut instrs = Code::default();
+        let held = scope.heap.get(&self.function);
+        if let Some(index) = held {
+            instrs.push(Op::copyFromHeap(index));
+        }
         for arg in &self.args {
             instrs.append(&mut arg.to_ops(scope));
         }
-        instrs.push(Op::invoke{name: self.function.clone(), args: self.args.len() as u64});
+        let args = self.args.len() as u64;
+        instrs.push(if held.is_some() { Op::invokeValue{args} } else { Op::invoke{name: self.function.clone(), args} });
         instrs
     }
 }
 
-impl Compilable for Conditional {
-    fn to_ops(&self, scope: &mut ScopeSizer) -> Vec<Op> {
-        let mut instrs = vec![];
-        let mut body = vec![];
-        scope.push();
+impl Conditional {
+    /// Runs the body and jumps to `end` if the condition holds, and otherwise
+    /// falls through to whatever comes next.
+    fn to_ops(&self, scope: &mut FunctionScope, end: Label) -> Code {
+        let mut instrs = Code::default();
+        let next = scope.label();
+        instrs.append(&mut self.condition.to_ops(scope));
+        instrs.push(Op::negatePrev);
+        instrs.push(Instr::JumpIf(next));
+        scope.heap.push();
         for b in &self.body {
-            body.append(&mut b.to_ops(scope));
+            instrs.append(&mut b.to_ops(scope));
         }
-        let scope_size = scope.pop();
+        let scope_size = scope.heap.pop();
         if scope_size > 0 {
-            body.push(Op::truncateHeap(scope_size as usize));
+            instrs.push(Op::truncateHeap(scope_size as usize));
         }
-        instrs.append(&mut self.condition.to_ops(scope));
-        instrs.append(&mut vec![
-            Op::negatePrev,
-            Op::conditonallySkipXops(body.len() as u64)
-        ]);
-        instrs.append(&mut body);
-        instrs.push(Op::noop);
+        instrs.push(Instr::Jump(end));
+        instrs.push(Instr::Label(next));
         instrs
     }
 }
\ No newline at end of file
@@ -0,0 +1,191 @@
+use crypto::ed25519;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use tuna_compiler::Compiled;
+use tuna_compiler::artifact::Format;
+use tuna_compiler::disasm;
+use tuna_interpreter::{Globals, State};
+use tuna_interpreter::data::InterpreterType;
+
+const USAGE: &str = "usage:
+    tuna check <file.tuna>
+    tuna build <file.tuna> [-o <out>] [--format json|binary]
+    tuna run <program> <function> [--args '<json array>'] [--key <keyfile>]
+    tuna disasm <program> [function]
+    tuna keygen [-o <keyfile>]
+
+<program> is either a .tuna source file or an artifact written by build.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("check") => check(&args[1..]),
+        Some("build") => build(&args[1..]),
+        Some("run") => run(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("keygen") => keygen(&args[1..]),
+        _ => Err(USAGE.to_string())
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+/// Positional arguments and `--flag value` pairs.
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<(String, String)>
+}
+
+impl Args {
+    fn parse(args: &[String], flags: &[&str]) -> Result<Args, String> {
+        let mut parsed = Args {positional: vec![], flags: vec![]};
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                parsed.positional.push(arg.clone());
+                continue;
+            }
+            if !flags.contains(&arg.as_str()) {
+                return Err(format!("Unknown option {}\n\n{}", arg, USAGE))
+            }
+            match args.next() {
+                Some(value) => parsed.flags.push((arg.clone(), value.clone())),
+                None => return Err(format!("{} needs a value", arg))
+            };
+        }
+        Ok(parsed)
+    }
+
+    fn flag(&self, names: &[&str]) -> Option<&str> {
+        self.flags.iter().rev().find(|(name, _)| names.contains(&name.as_str())).map(|(_, value)| value.as_str())
+    }
+
+    fn positional(&self, index: usize, what: &str) -> Result<&str, String> {
+        self.positional.get(index).map(|p| p.as_str()).ok_or_else(|| format!("Missing {}\n\n{}", what, USAGE))
+    }
+}
+
+fn compile(path: &str) -> Result<Compiled, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    tuna_compiler::compile_file(path, &source).map_err(|errors| {
+        errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("\n")
+    })
+}
+
+/// Compiles source files and loads anything else as an artifact.
+fn load(path: &str) -> Result<Compiled, String> {
+    match Path::new(path).extension() {
+        Some(ext) if ext == "tuna" => compile(path),
+        _ => Compiled::load(Path::new(path))
+    }
+}
+
+fn check(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &[])?;
+    compile(args.positional(0, "source file")?).map(|_| ())
+}
+
+fn build(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["-o", "--out", "--format"])?;
+    let source = args.positional(0, "source file")?;
+    let (format, ext) = match args.flag(&["--format"]) {
+        None | Some("json") => (Format::Json, "json"),
+        Some("binary") => (Format::Binary, "tunac"),
+        Some(other) => return Err(format!("Unknown format {}, expected json or binary", other))
+    };
+    let out = match args.flag(&["-o", "--out"]) {
+        Some(out) => PathBuf::from(out),
+        None => Path::new(source).with_extension(ext)
+    };
+    compile(source)?.save(&out, format)
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["--args", "--key"])?;
+    let program = load(args.positional(0, "program")?)?;
+    let function = args.positional(1, "function name")?.to_string();
+    let mut inputs: Vec<InterpreterType> = match args.flag(&["--args"]) {
+        Some(json) => serde_json::from_str(json).map_err(|e| format!("--args must be a JSON array: {}", e))?,
+        None => vec![]
+    };
+    let (private_key, public_key) = match args.flag(&["--key"]) {
+        Some(path) => read_key(path)?,
+        None => ed25519::keypair(&seed()?)
+    };
+
+    let globals = Globals::new(&program.schemas, &program.fns, &program.lambdas, &private_key, &public_key)?;
+    let result = globals.run(&function, &mut State::new(&mut inputs))?;
+    println!("{}", serde_json::to_string(&result).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+fn disasm(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &[])?;
+    let program = load(args.positional(0, "program")?)?;
+    let ops = |name: &String| program.fns.get(name).or_else(|| program.lambdas.get(name));
+    let mut names: Vec<&String> = match args.positional.get(1) {
+        Some(name) if ops(name).is_some() => vec![name],
+        Some(name) => return Err(format!("Unknown function {}", name)),
+        None => program.fns.keys().chain(program.lambdas.keys()).collect()
+    };
+    names.sort();
+    for name in names {
+        let slots = program.functions.get(name).map(|f| f.slots.as_slice()).unwrap_or(&[]);
+        println!("; func {}", name);
+        print!("{}", disasm::disassemble(ops(name).unwrap(), slots));
+    }
+    Ok(())
+}
+
+fn seed() -> Result<[u8; 32], String> {
+    let mut seed = [0u8; 32];
+    SystemRandom::new().fill(&mut seed).map_err(|_| "Could not generate a key".to_string())?;
+    Ok(seed)
+}
+
+/// Writes a fresh signing key pair as JSON with hex-encoded keys.
+fn keygen(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["-o", "--out"])?;
+    let (private_key, public_key) = ed25519::keypair(&seed()?);
+    let key = serde_json::json!({
+        "private_key": to_hex(&private_key),
+        "public_key": to_hex(&public_key)
+    }).to_string();
+    match args.flag(&["-o", "--out"]) {
+        Some(out) => fs::write(out, key).map_err(|e| format!("Could not write {}: {}", out, e)),
+        None => {
+            println!("{}", key);
+            Ok(())
+        }
+    }
+}
+
+fn read_key(path: &str) -> Result<([u8; 64], [u8; 32]), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let key: serde_json::Value = serde_json::from_str(&text).map_err(|e| format!("Bad key file {}: {}", path, e))?;
+    let field = |name: &str| key[name].as_str().and_then(from_hex).ok_or_else(|| format!("Bad key file {}: missing {}", path, name));
+    let (private, public) = (field("private_key")?, field("public_key")?);
+    if private.len() != 64 || public.len() != 32 {
+        return Err(format!("Bad key file {}: wrong key length", path))
+    }
+    let (mut private_key, mut public_key) = ([0u8; 64], [0u8; 32]);
+    private_key.copy_from_slice(&private);
+    public_key.copy_from_slice(&public);
+    Ok((private_key, public_key))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes().chunks(2).map(|pair| match pair.len() {
+        2 => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+        _ => None
+    }).collect()
+}
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use tuna_interpreter::schemas::Schema;
+use crate::ir::*;
+
+/// The names a lambda uses without defining them, in the order they're first used.
+/// Those that are variables where the lambda is defined are the ones it captures;
+/// the rest are functions. Names used by lambdas nested inside it count too, since
+/// it has to capture them to pass them on.
+pub(crate) fn free_names(args: &[(Schema, String)], body: &[Root]) -> Vec<String> {
+    let mut finder = Finder {
+        bound: vec![args.iter().map(|(_, name)| name.clone()).collect()],
+        free: vec![]
+    };
+    finder.block(body);
+    finder.free
+}
+
+struct Finder {
+    /// Names defined in each enclosing block of the lambda.
+    bound: Vec<HashSet<String>>,
+    free: Vec<String>
+}
+
+impl Finder {
+    fn uses(&mut self, name: &str) {
+        let bound = self.bound.iter().any(|names| names.contains(name));
+        if !bound && !self.free.iter().any(|free| free == name) {
+            self.free.push(name.to_string());
+        }
+    }
+
+    fn block(&mut self, body: &[Root]) {
+        self.bound.push(HashSet::new());
+        for root in body {
+            self.root(root);
+        }
+        self.bound.pop();
+    }
+
+    fn root(&mut self, root: &Root) {
+        match root {
+            Root::Branch(conds) => {
+                for cond in conds {
+                    self.value(&cond.condition);
+                    self.block(&cond.body);
+                }
+            },
+            Root::Save{val, name} => {
+                self.value(val);
+                self.bound.last_mut().unwrap().insert(name.clone());
+            },
+            Root::Update{root, level, operation} => {
+                self.uses(&root.0);
+                for l in level {
+                    self.value(l);
+                }
+                match operation {
+                    Mut::Overwrite(val) => self.value(val),
+                    Mut::Push(vals) => for v in vals {
+                        self.value(v);
+                    },
+                    Mut::Delete => {}
+                };
+            },
+            Root::ForEach{target, body, arg, value} => {
+                self.value(target);
+                self.bound.push(std::iter::once(arg).chain(value.iter()).cloned().collect());
+                self.block(body);
+                self.bound.pop();
+            },
+            Root::While{condition, body} => {
+                self.value(condition);
+                self.block(body);
+            },
+            Root::Break | Root::Continue => {},
+            Root::Call(call) => self.call(call),
+            Root::Discard(v) => self.value(v),
+            Root::Return(v) => if let Some(v) = v {
+                self.value(v);
+            }
+        };
+    }
+
+    fn call(&mut self, call: &Call) {
+        self.uses(&call.function);
+        for arg in &call.args {
+            self.value(arg);
+        }
+    }
+
+    fn value(&mut self, value: &AnyValue) {
+        match value {
+            AnyValue::Bool(_) | AnyValue::Int(_) | AnyValue::Double(_) | AnyValue::String(_) | AnyValue::None => {},
+            AnyValue::Object(fields) | AnyValue::RoleInstance{data: fields, ..} => for field in fields {
+                self.value(&field.value);
+            },
+            AnyValue::GetType(v) | AnyValue::Not(v) | AnyValue::Negate(v) | AnyValue::Keys(v) |
+            AnyValue::Len(v) | AnyValue::Is{val: v, ..} => self.value(v),
+            AnyValue::BinaryOp{left, right, ..} => {
+                self.value(left);
+                self.value(right);
+            },
+            AnyValue::Has{val, key} => {
+                self.value(val);
+                self.value(key);
+            },
+            AnyValue::Saved(name) => self.uses(name),
+            AnyValue::Selection{root: target, level: args} | AnyValue::ArrayMethod{target, args, ..} => {
+                self.value(target);
+                for arg in args {
+                    self.value(arg);
+                }
+            },
+            AnyValue::Array(vals) => for v in vals {
+                self.value(v);
+            },
+            AnyValue::Call(call) => self.call(call),
+            AnyValue::Lambda{args, body} => {
+                self.bound.push(args.iter().map(|(_, name)| name.clone()).collect());
+                self.block(body);
+                self.bound.pop();
+            }
+        };
+    }
+}
@@ -0,0 +1,123 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use tuna_interpreter::ops::Op;
+pub use tuna_interpreter::verify::{heap_slot, jump_target};
+
+/// Prints one op per line with its index. Jump targets are printed as labels
+/// named after the index they land on, and ops that touch the heap are
+/// annotated with the variables `slots` says were stored there.
+pub fn disassemble(ops: &[Op], slots: &[Vec<String>]) -> String {
+    let targets: BTreeSet<usize> = ops.iter().enumerate().filter_map(|(i, op)| jump_target(i, op)).collect();
+    let mut out = String::new();
+    for (i, op) in ops.iter().enumerate() {
+        if targets.contains(&i) {
+            out.push_str(&format!("L{}:\n", i));
+        }
+        let mut line = format!("{:>6}  {}", i, mnemonic(i, op));
+        match heap_slot(op).and_then(|slot| slots.get(slot)) {
+            Some(names) if !names.is_empty() => line.push_str(&format!("  ; {}", names.join(" | "))),
+            _ => {}
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    if targets.contains(&ops.len()) {
+        out.push_str(&format!("L{}:\n", ops.len()));
+    }
+    out
+}
+
+fn mnemonic(index: usize, op: &Op) -> String {
+    let encoded = serde_json::to_value(op).unwrap();
+    let kind = encoded["kind"].as_str().unwrap();
+    match (jump_target(index, op), encoded.get("data")) {
+        (Some(target), _) => format!("{} L{}", kind, target),
+        (None, Some(data)) => format!("{} {}", kind, data),
+        (None, None) => kind.to_string()
+    }
+}
+
+enum Operand {
+    None,
+    Label(String),
+    Data(Value)
+}
+
+/// Parses the output of `disassemble` back into ops. Op indices and `;`
+/// comments are optional, and labels may have any name.
+pub fn assemble(text: &str) -> Result<Vec<Op>, String> {
+    let mut labels = HashMap::new();
+    let mut lines = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let at = |message: String| format!("line {}: {}", number + 1, message);
+        if let Some(label) = line.strip_suffix(':').filter(|l| is_label(l)) {
+            if labels.insert(label.to_string(), lines.len()).is_some() {
+                return Err(at(format!("Label {} is defined twice", label)))
+            }
+            continue;
+        }
+        let line = line.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start();
+        let (kind, rest) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+        let (operand, rest) = operand(rest.trim_start()).map_err(at)?;
+        if !rest.is_empty() && !rest.starts_with(';') {
+            return Err(at(format!("Unexpected {}", rest)))
+        }
+        lines.push((number + 1, kind.to_string(), operand));
+    }
+
+    let mut ops = Vec::with_capacity(lines.len());
+    for (index, (number, kind, operand)) in lines.into_iter().enumerate() {
+        let at = |message: String| format!("line {}: {}", number, message);
+        let data = match operand {
+            Operand::Label(label) => {
+                let target = *labels.get(&label).ok_or_else(|| at(format!("Unknown label {}", label)))?;
+                jump(&kind, index, target).map_err(at)?
+            },
+            Operand::Data(data) => Some(data),
+            Operand::None => None
+        };
+        let encoded = match data {
+            Some(data) => json!({"kind": kind, "data": data}),
+            None => json!({"kind": kind})
+        };
+        ops.push(serde_json::from_value(encoded).map_err(|e| at(e.to_string()))?);
+    }
+    Ok(ops)
+}
+
+fn is_label(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic()) && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits the operand off the rest of a line. Jumps take a label, and
+/// everything else takes the JSON its `data` serializes to.
+fn operand(text: &str) -> Result<(Operand, &str), String> {
+    if text.is_empty() || text.starts_with(';') {
+        return Ok((Operand::None, text))
+    }
+    let word = &text[..text.find(char::is_whitespace).unwrap_or(text.len())];
+    if is_label(word) && !["true", "false", "null"].contains(&word) {
+        return Ok((Operand::Label(word.to_string()), text[word.len()..].trim_start()))
+    }
+    let mut values = serde_json::Deserializer::from_str(text).into_iter::<Value>();
+    let value = match values.next() {
+        Some(Ok(value)) => value,
+        Some(Err(e)) => return Err(format!("Bad operand: {}", e)),
+        None => return Err("Missing operand".to_string())
+    };
+    Ok((Operand::Data(value), text[values.byte_offset()..].trim_start()))
+}
+
+fn jump(kind: &str, index: usize, target: usize) -> Result<Option<Value>, String> {
+    match kind {
+        "offsetOpCursor" if target > index => Ok(Some(json!({"offset": target - index - 1, "fwd": true}))),
+        "offsetOpCursor" => Ok(Some(json!({"offset": index - target, "fwd": false}))),
+        "conditonallySkipXops" if target > index => Ok(Some(json!(target - index - 1))),
+        "conditonallySkipXops" => Err("conditonallySkipXops can only jump forward".to_string()),
+        _ => Err(format!("{} does not take a label", kind))
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use pest::error::{Error, ErrorVariant, LineColLocation};
+use pest::iterators::Pair;
+use crate::Rule;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize
+}
+
+impl Span {
+    pub fn of(pair: &Pair<Rule>) -> Span {
+        let span = pair.as_span();
+        let (line, column) = span.start_pos().line_col();
+        let (end_line, end_column) = span.end_pos().line_col();
+        Span {
+            file: String::new(),
+            line,
+            column,
+            end_line,
+            end_column
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    Syntax,
+    Unsupported,
+    InvalidLiteral,
+    InvalidTarget,
+    UnknownType,
+    UnknownFunction,
+    WrongArity,
+    DuplicateDefinition,
+    UndefinedName,
+    AssignToConst,
+    TypeMismatch,
+    OutsideLoop,
+    ShadowedFunction
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "E0001",
+            ErrorCode::Unsupported => "E0002",
+            ErrorCode::InvalidLiteral => "E0003",
+            ErrorCode::InvalidTarget => "E0004",
+            ErrorCode::UnknownType => "E0005",
+            ErrorCode::UnknownFunction => "E0006",
+            ErrorCode::WrongArity => "E0007",
+            ErrorCode::DuplicateDefinition => "E0008",
+            ErrorCode::UndefinedName => "E0009",
+            ErrorCode::AssignToConst => "E0010",
+            ErrorCode::TypeMismatch => "E0011",
+            ErrorCode::OutsideLoop => "E0012",
+            ErrorCode::ShadowedFunction => "E0013"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span
+}
+
+pub type Diagnostics = Vec<CompileError>;
+
+impl CompileError {
+    pub fn new(code: ErrorCode, message: String, span: Span) -> CompileError {
+        CompileError {
+            code,
+            message,
+            span
+        }
+    }
+
+    pub fn at(code: ErrorCode, pair: &Pair<Rule>, message: String) -> CompileError {
+        CompileError::new(code, message, Span::of(pair))
+    }
+
+    pub fn unsupported(pair: &Pair<Rule>, construct: &str) -> CompileError {
+        CompileError::at(ErrorCode::Unsupported, pair, format!("{} are not supported yet", construct))
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: error[{}]: {}", self.span.file, self.span.line, self.span.column, self.code.code(), self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<Error<Rule>> for CompileError {
+    fn from(e: Error<Rule>) -> CompileError {
+        let ((line, column), (end_line, end_column)) = match e.line_col {
+            LineColLocation::Pos(pos) => (pos, pos),
+            LineColLocation::Span(start, end) => (start, end)
+        };
+        let message = match e.variant {
+            ErrorVariant::ParsingError{positives, ..} => {
+                let expected: Vec<String> = positives.iter().map(|r| format!("{:?}", r)).collect();
+                if expected.is_empty() {
+                    "unexpected input".to_string()
+                } else {
+                    format!("expected {}", expected.join(", "))
+                }
+            },
+            ErrorVariant::CustomError{message} => message
+        };
+        CompileError::new(ErrorCode::Syntax, message, Span {
+            file: String::new(),
+            line,
+            column,
+            end_line,
+            end_column
+        })
+    }
+}
+
+impl From<CompileError> for Diagnostics {
+    fn from(e: CompileError) -> Diagnostics {
+        vec![e]
+    }
+}
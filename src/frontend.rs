@@ -0,0 +1,682 @@
+use pest::iterators::{Pair, Pairs};
+use std::iter::Peekable;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use tuna_interpreter::schemas::{Schema, ObjSchema};
+use crate::Rule;
+use crate::ir::{self, *};
+use crate::error::{CompileError, Diagnostics, ErrorCode, Span};
+
+pub(crate) type Lowered<T> = Result<T, Diagnostics>;
+
+pub(crate) trait Tuna<T> {
+    fn tunify(self) -> Lowered<T>;
+}
+
+pub(crate) type Token<'a> = Pair<'a, Rule>;
+
+/// Lowers every pair, reporting the errors of all of them rather than just the first.
+fn tunify_all<'a, T>(pairs: Pairs<'a, Rule>) -> Lowered<Vec<T>> where Token<'a>: Tuna<T> {
+    let mut lowered = vec![];
+    let mut errors = vec![];
+    for p in pairs {
+        match p.tunify() {
+            Ok(t) => lowered.push(t),
+            Err(mut e) => errors.append(&mut e)
+        };
+    }
+    if errors.is_empty() {
+        Ok(lowered)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Combines two independently lowered parts, keeping the errors of both.
+fn both<A, B>(a: Lowered<A>, b: Lowered<B>) -> Lowered<(A, B)> {
+    match (a, b) {
+        (Ok(a), Ok(b)) => Ok((a, b)),
+        (a, b) => {
+            let mut errors = a.err().unwrap_or_default();
+            errors.append(&mut b.err().unwrap_or_default());
+            Err(errors)
+        }
+    }
+}
+
+impl<'a> Tuna<Vec<(Schema, String)>> for Token<'a> {
+    fn tunify(self) -> Lowered<Vec<(Schema, String)>> {
+        match self.as_rule() {
+            Rule::params => {
+                let mut v = vec![];
+                for param in self.into_inner() {
+                    match param.as_rule() {
+                        Rule::name => v.push((Schema::Any, param.as_str().to_string())),
+                        Rule::schema => v.last_mut().unwrap().0 = param.tunify()?,
+                        _ => unreachable!()
+                    };
+                }
+                Ok(v)
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+impl<'a> Tuna<Schema> for Token<'a> {
+    fn tunify(self) -> Lowered<Schema> {
+        match self.as_rule() {
+            Rule::schema => self.into_inner().next().unwrap().tunify(),
+            Rule::object_t => {
+                let mut fields = HashMap::new();
+                for field in self.into_inner() {
+                    let mut field = field.into_inner();
+                    let name = field.next().unwrap().as_str().to_string();
+                    fields.insert(name, field.next().unwrap().tunify()?);
+                }
+                Ok(Schema::Object(ObjSchema(fields)))
+            },
+            Rule::someType => {
+                let mut schema = Schema::Any;
+                for part in self.into_inner() {
+                    match part.as_rule() {
+                        Rule::typeBody => {
+                            let body = part.into_inner().next().unwrap();
+                            schema = match body.as_rule() {
+                                Rule::str_t => Schema::string,
+                                Rule::int_t => Schema::int,
+                                Rule::double_t => Schema::double,
+                                Rule::bool_t => Schema::bool,
+                                Rule::any_t => Schema::Any,
+                                Rule::func_t => Schema::Function,
+                                Rule::object_t => body.tunify()?,
+                                Rule::name => Schema::TypeAlias(body.as_str().to_string()),
+                                _ => unreachable!()
+                            };
+                        },
+                        Rule::typePostfix => {
+                            schema = match part.into_inner().next().unwrap().as_rule() {
+                                Rule::array_t => Schema::Array(vec![schema]),
+                                Rule::optional_t => Schema::Union(vec![schema, Schema::none]),
+                                _ => unreachable!()
+                            };
+                        },
+                        Rule::union_t => {
+                            let other: Schema = part.into_inner().nth(1).unwrap().tunify()?;
+                            let mut options = vec![schema];
+                            match other {
+                                Schema::Union(mut rest) => options.append(&mut rest),
+                                other => options.push(other)
+                            };
+                            schema = Schema::Union(options);
+                        },
+                        _ => unreachable!()
+                    };
+                }
+                Ok(schema)
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+impl<'a> Tuna<Vec<Box<AnyValue>>> for Token<'a> {
+    fn tunify(self) -> Lowered<Vec<Box<AnyValue>>> {
+        match self.as_rule() {
+            Rule::args => tunify_all(self.into_inner()),
+            _ => unreachable!()
+        }
+    }
+}
+
+impl<'a> Tuna<Call> for Token<'a> {
+    fn tunify(self) -> Lowered<Call> {
+        match self.as_rule() {
+            Rule::functionCall => {
+                let mut name = None;
+                let mut args = vec![];
+                for p in self.into_inner() {
+                    match p.as_rule() {
+                        Rule::name => name = Some(p.as_str()),
+                        Rule::args => args = p.tunify()?,
+                        _ => unreachable!()
+                    };
+                }
+                Ok(Call {
+                    function: name.unwrap().to_string(),
+                    args
+                })
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+fn sign_of(infix: Token) -> Sign {
+    match infix.into_inner().peek().unwrap().as_rule() {
+        Rule::plus => Sign::Plus,
+        Rule::minus => Sign::Minus,
+        Rule::mult => Sign::Mult,
+        Rule::divide => Sign::Div,
+        Rule::eq => Sign::Eq,
+        Rule::neq => Sign::Neq,
+        Rule::leq => Sign::Leq,
+        Rule::geq => Sign::Geq,
+        Rule::gt => Sign::G,
+        Rule::lt => Sign::L,
+        Rule::and => Sign::And,
+        Rule::or => Sign::Or,
+        _ => unreachable!()
+    }
+}
+
+/// How tightly an infix operator binds its operands; higher binds tighter.
+fn binding_power(sign: &Sign) -> u8 {
+    match sign {
+        Sign::Or => 1,
+        Sign::And => 2,
+        Sign::Eq | Sign::Neq | Sign::L | Sign::G | Sign::Leq | Sign::Geq => 3,
+        Sign::Plus | Sign::Minus => 4,
+        Sign::Mult | Sign::Div => 5
+    }
+}
+
+/// Combines the operands of an expression, so lowering and type checking
+/// group operators the same way.
+pub(crate) trait Climber<'a> {
+    type Output;
+    fn operand(&mut self, operand: Token<'a>) -> Self::Output;
+    fn not(&mut self, not: Token<'a>, operand: Self::Output) -> Self::Output;
+    fn binary(&mut self, infix: Token<'a>, sign: Sign, left: Self::Output, right: Self::Output) -> Self::Output;
+}
+
+/// Precedence climbing over the alternating operands and infix operators of an expression.
+/// Operators of equal binding power associate to the left.
+pub(crate) fn climb<'a, C: Climber<'a>>(climber: &mut C, pairs: &mut Peekable<Pairs<'a, Rule>>, min_power: u8) -> C::Output {
+    let first = pairs.next().unwrap();
+    let mut left = match first.as_rule() {
+        // `not` takes in comparisons but not `and`/`or`, so `not a == b and c` is `(not (a == b)) and c`.
+        Rule::not => {
+            let operand = climb(climber, pairs, binding_power(&Sign::And));
+            climber.not(first, operand)
+        },
+        _ => climber.operand(first)
+    };
+    while let Some(infix) = pairs.peek() {
+        let sign = sign_of(infix.clone());
+        let power = binding_power(&sign);
+        if power <= min_power {
+            break;
+        }
+        let infix = pairs.next().unwrap();
+        let right = climb(climber, pairs, power);
+        left = climber.binary(infix, sign, left, right);
+    }
+    left
+}
+
+struct Lowering;
+
+impl<'a> Climber<'a> for Lowering {
+    type Output = Lowered<Box<AnyValue>>;
+
+    fn operand(&mut self, operand: Token<'a>) -> Self::Output {
+        operand.tunify()
+    }
+
+    fn not(&mut self, _: Token<'a>, operand: Self::Output) -> Self::Output {
+        Ok(Box::new(AnyValue::Not(operand?)))
+    }
+
+    fn binary(&mut self, _: Token<'a>, sign: Sign, left: Self::Output, right: Self::Output) -> Self::Output {
+        let (left, right) = both(left, right)?;
+        Ok(Box::new(AnyValue::BinaryOp{sign, left, right}))
+    }
+}
+
+/// The parts of an operand. Its methods apply to the body first, then the negations,
+/// so `-a.len()` negates the length.
+pub(crate) struct Operand<'a> {
+    pub negations: Vec<Token<'a>>,
+    pub body: Token<'a>,
+    pub methods: Vec<Token<'a>>
+}
+
+impl<'a> Operand<'a> {
+    pub fn of(operand: Token<'a>) -> Operand<'a> {
+        let mut negations = vec![];
+        let mut methods = vec![];
+        let mut body = None;
+        for p in operand.into_inner() {
+            match p.as_rule() {
+                Rule::negate => negations.push(p),
+                Rule::method => methods.push(p.into_inner().next().unwrap()),
+                _ => body = Some(p)
+            };
+        }
+        Operand {negations, body: body.unwrap(), methods}
+    }
+
+    /// The number literal a single leading minus is part of, which lets `i64::MIN` be
+    /// written. Methods bind to the literal before the minus, so then it isn't folded.
+    pub fn negative_number(&self) -> Option<Token<'a>> {
+        if self.negations.len() != 1 || !self.methods.is_empty() || self.body.as_rule() != Rule::literal {
+            return None
+        }
+        Some(self.body.clone().into_inner().next().unwrap()).filter(|lit| lit.as_rule() == Rule::num)
+    }
+}
+
+/// Lowers a number literal, which is an `Int` unless written with a fraction or exponent.
+/// `negative` folds a leading minus into the literal so that `i64::MIN` can be written.
+fn number(num: Token, negative: bool) -> Lowered<AnyValue> {
+    let lit = num.clone().into_inner().next().unwrap().into_inner().next().unwrap();
+    let digits: String = lit.as_str().chars().filter(|c| *c != '_').collect();
+    let sign = if negative { "-" } else { "" };
+    let parsed = match lit.as_rule() {
+        Rule::decimal => return match f64::from_str(&digits) {
+            Ok(d) if d.is_finite() => Ok(AnyValue::Double(if negative { -d } else { d })),
+            _ => Err(CompileError::at(ErrorCode::InvalidLiteral, &num, format!("Number {}{} is too large", sign, num.as_str())).into())
+        },
+        Rule::integer => format!("{}{}", sign, digits).parse::<i64>(),
+        Rule::hex => i64::from_str_radix(&format!("{}{}", sign, &digits[2..]), 16),
+        Rule::binary => i64::from_str_radix(&format!("{}{}", sign, &digits[2..]), 2),
+        _ => unreachable!()
+    };
+    match parsed {
+        Ok(i) => Ok(AnyValue::Int(i)),
+        Err(_) => Err(CompileError::at(ErrorCode::InvalidLiteral, &num, format!("Integer {}{} does not fit in 64 bits", sign, num.as_str())).into())
+    }
+}
+
+fn literal(lit: Token) -> Lowered<AnyValue> {
+    Ok(match lit.as_rule() {
+        Rule::object => {
+            let mut fields = vec![];
+            let mut name = None;
+            for field in lit.into_inner() {
+                match field.as_rule() {
+                    Rule::name => name = Some(field.as_str().to_string()),
+                    Rule::expression => {
+                        fields.push(Field {
+                            key: name.unwrap(),
+                            value: field.tunify()?
+                        });
+                        name = None;
+                    },
+                    _ => unreachable!()
+                };
+            }
+            AnyValue::Object(fields)
+        },
+        Rule::string => {
+            let full = lit.as_str();
+            AnyValue::String(full[1..full.len() - 1].to_string())
+        },
+        Rule::boolean => AnyValue::Bool(lit.as_str() == "true"),
+        Rule::num => number(lit, false)?,
+        Rule::none => AnyValue::None,
+        Rule::array => AnyValue::Array(tunify_all(lit.into_inner())?),
+        _ => unreachable!()
+    })
+}
+
+impl<'a> Tuna<Box<AnyValue>> for Token<'a> {
+    fn tunify(self) -> Lowered<Box<AnyValue>> {
+        let val = match self.as_rule() {
+            Rule::expression => return climb(&mut Lowering, &mut self.into_inner().peekable(), 0),
+            Rule::operand => {
+                let operand = Operand::of(self);
+                if let Some(num) = operand.negative_number() {
+                    return Ok(Box::new(number(num, true)?))
+                }
+                let p = operand.body;
+                let mut body = match p.as_rule() {
+                    Rule::literal => literal(p.into_inner().next().unwrap())?,
+                    Rule::expression => *climb(&mut Lowering, &mut p.into_inner().peekable(), 0)?,
+                    Rule::functionCall => AnyValue::Call(p.tunify()?),
+                    Rule::lambda => lambda(p)?,
+                    Rule::roleInstance => {
+                        let mut parts = p.into_inner();
+                        let name = parts.next().unwrap().as_str().to_string();
+                        let data = match literal(parts.next().unwrap())? {
+                            AnyValue::Object(fields) => fields,
+                            _ => unreachable!()
+                        };
+                        AnyValue::RoleInstance{name, data}
+                    },
+                    Rule::name => AnyValue::Saved(p.as_str().to_string()),
+                    _ => unreachable!()
+                };
+                for m in operand.methods {
+                    body = method(body, m)?;
+                }
+                for _ in operand.negations {
+                    body = AnyValue::Negate(Box::new(body));
+                }
+                body
+            },
+            _ => unreachable!()
+        };
+        Ok(Box::new(val))
+    }
+}
+
+/// A lambda with a block body, or an expression body it returns.
+fn lambda(token: Token) -> Lowered<AnyValue> {
+    let mut parts = token.into_inner();
+    let args = parts.next().unwrap().tunify();
+    let body = parts.next().unwrap();
+    let body = match body.as_rule() {
+        Rule::scope => body.tunify().map(into_roots),
+        _ => body.tunify().map(|value| vec![Root::Return(Some(value))])
+    };
+    let (args, body) = both(args, body)?;
+    Ok(AnyValue::Lambda {args, body})
+}
+
+/// Methods built into every value besides those of arrays, with the number of arguments each takes.
+/// `push` mutates a variable, so it isn't listed here and is only lowered as a statement.
+const BUILTIN_METHODS: [(&str, RangeInclusive<usize>); 3] = [("keys", 0..=0), ("len", 0..=0), ("has", 1..=1)];
+
+/// Whether `x.name()` calls a built in method rather than the function `name`.
+pub(crate) fn is_builtin_method(name: &str) -> bool {
+    name == "push" || ArrayMethod::named(name).is_some() || BUILTIN_METHODS.iter().any(|(builtin, _)| *builtin == name)
+}
+
+fn check_arity(m: &Token, name: &str, arity: RangeInclusive<usize>, given: usize) -> Lowered<()> {
+    if arity.contains(&given) {
+        return Ok(())
+    }
+    let takes = match (arity.start(), arity.end()) {
+        (min, max) if min == max => min.to_string(),
+        (min, max) => format!("{} to {}", min, max)
+    };
+    Err(CompileError::at(ErrorCode::WrongArity, m, format!("{}() takes {} argument(s) but {} were given", name, takes, given)).into())
+}
+
+/// Applies a field access, index or method call to `target`.
+/// Methods that aren't built in call the function of that name with `target` as the first argument.
+fn method(target: AnyValue, m: Token) -> Lowered<AnyValue> {
+    let field = match m.as_rule() {
+        Rule::fieldAccess => Box::new(AnyValue::String(m.into_inner().next().unwrap().as_str().to_string())),
+        Rule::parameterIndex => m.into_inner().next().unwrap().tunify()?,
+        Rule::methodInvoke => {
+            let mut parts = m.clone().into_inner();
+            let name = parts.next().unwrap().as_str();
+            let mut args: Vec<Value> = parts.next().unwrap().tunify()?;
+            let target = Box::new(target);
+            if name == "push" {
+                return Err(CompileError::at(ErrorCode::InvalidTarget, &m, "push() can only be used as a statement on a variable".to_string()).into())
+            }
+            if let Some(method) = ArrayMethod::named(name) {
+                check_arity(&m, name, method.arity(), args.len())?;
+                return Ok(AnyValue::ArrayMethod{method, target, args});
+            }
+            match BUILTIN_METHODS.iter().find(|(builtin, _)| *builtin == name) {
+                Some((_, arity)) => check_arity(&m, name, arity.clone(), args.len())?,
+                None => {
+                    args.insert(0, target);
+                    return Ok(AnyValue::Call(Call {
+                        function: name.to_string(),
+                        args
+                    }));
+                }
+            };
+            return Ok(match name {
+                "keys" => AnyValue::Keys(target),
+                "len" => AnyValue::Len(target),
+                // The last of BUILTIN_METHODS.
+                _ => AnyValue::Has{val: target, key: args.pop().unwrap()}
+            });
+        },
+        _ => unreachable!()
+    };
+    Ok(match target {
+        AnyValue::Selection{root, mut level} => {
+            level.push(field);
+            AnyValue::Selection{root, level}
+        },
+        root => AnyValue::Selection{root: Box::new(root), level: vec![field]}
+    })
+}
+
+impl<'a> Tuna<ValueOrRoot> for Token<'a> {
+    fn tunify(self) -> Lowered<ValueOrRoot> {
+        match self.as_rule() {
+            Rule::ret => {
+                let mut exp = None;
+                for i in self.into_inner() {
+                    match i.as_rule() {
+                        Rule::expression => exp = Some(i.tunify()?),
+                        _ => unreachable!()
+                    };
+                }
+                Ok(Either::Left(Root::Return(exp)))
+            },
+            Rule::var => {
+                let mut exp = None;
+                let mut name = None;
+                for i in self.into_inner() {
+                    match i.as_rule() {
+                        Rule::mutable | Rule::constant => {},
+                        Rule::name => name = Some(i.as_str()),
+                        Rule::expression => exp = Some(i.tunify()?),
+                        _ => unreachable!()
+                    };
+                }
+                Ok(Either::Left(
+                    Root::Save{val: exp.unwrap(), name: name.unwrap().to_string()}
+                ))
+            },
+            Rule::forLoop => {
+                let mut names = vec![];
+                let mut target = None;
+                let mut body = None;
+                for i in self.into_inner() {
+                    match i.as_rule() {
+                        Rule::name => names.push(i.as_str().to_string()),
+                        Rule::expression => target = Some(i.tunify()),
+                        Rule::scope => body = Some(i.tunify()),
+                        _ => unreachable!()
+                    };
+                }
+                let mut names = names.into_iter();
+                let (target, body): (_, Vec<ValueOrRoot>) = both(target.unwrap(), body.unwrap())?;
+                Ok(Either::Left(Root::ForEach {
+                    target,
+                    body: into_roots(body),
+                    arg: names.next().unwrap(),
+                    value: names.next()
+                }))
+            },
+            Rule::whileLoop => {
+                let mut parts = self.into_inner();
+                let condition = parts.next().unwrap().tunify();
+                let body = parts.next().unwrap().tunify();
+                let (condition, body): (_, Vec<ValueOrRoot>) = both(condition, body)?;
+                Ok(Either::Left(Root::While {condition, body: into_roots(body)}))
+            },
+            Rule::breakLoop => Ok(Either::Left(Root::Break)),
+            Rule::continueLoop => Ok(Either::Left(Root::Continue)),
+            Rule::ifs => {
+                let mut conds = vec![];
+                for branch in self.into_inner() {
+                    conds.push(match branch.as_rule() {
+                        Rule::conditional => branch.tunify()?,
+                        Rule::elif => branch.into_inner().next().unwrap().tunify()?,
+                        Rule::otherwise => {
+                            let body: Vec<ValueOrRoot> = branch.into_inner().next().unwrap().tunify()?;
+                            Conditional {
+                                condition: Box::new(AnyValue::Bool(true)),
+                                body: into_roots(body)
+                            }
+                        },
+                        _ => unreachable!()
+                    });
+                }
+                Ok(Either::Left(Root::Branch(conds)))
+            },
+            Rule::assignment => {
+                let mut parts = self.into_inner();
+                let target = path(parts.next().unwrap());
+                let ((root, level), val) = both(target, parts.next().unwrap().tunify())?;
+                Ok(Either::Left(Root::Update{root, level, operation: Mut::Overwrite(val)}))
+            },
+            Rule::delete => {
+                let target = self.into_inner().next().unwrap();
+                let (root, level) = path(target.clone())?;
+                if level.is_empty() {
+                    return Err(CompileError::at(ErrorCode::InvalidTarget, &target, format!("Only fields and elements can be deleted, not {}", target.as_str())).into());
+                }
+                Ok(Either::Left(Root::Update{root, level, operation: Mut::Delete}))
+            },
+            Rule::expression => {
+                if let Some(push) = push_into_path(self.clone()) {
+                    return push;
+                }
+                Ok(Either::Right(self.tunify()?))
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+fn invalid_target(target: &Token) -> Diagnostics {
+    CompileError::at(ErrorCode::InvalidTarget, target, format!("{} cannot be assigned to", target.as_str())).into()
+}
+
+/// Splits an assignable expression such as `a.b[c]` into the variable being mutated and the fields leading into it.
+fn path(target: Token) -> Lowered<(Saved, Vec<Value>)> {
+    let mut parts = target.clone().into_inner();
+    let operand = parts.next().unwrap();
+    if parts.next().is_some() {
+        return Err(invalid_target(&target));
+    }
+    path_of(operand.into_inner(), &target)
+}
+
+fn path_of<'a>(parts: impl Iterator<Item=Token<'a>>, target: &Token) -> Lowered<(Saved, Vec<Value>)> {
+    let mut root = None;
+    let mut level = vec![];
+    for p in parts {
+        match p.as_rule() {
+            Rule::name => root = Some(Saved(p.as_str().to_string())),
+            Rule::method => {
+                let m = p.into_inner().next().unwrap();
+                match m.as_rule() {
+                    Rule::fieldAccess => level.push(Box::new(AnyValue::String(m.into_inner().next().unwrap().as_str().to_string()))),
+                    Rule::parameterIndex => level.push(m.into_inner().next().unwrap().tunify()?),
+                    _ => return Err(invalid_target(target))
+                };
+            },
+            _ => return Err(invalid_target(target))
+        };
+    }
+    match root {
+        Some(root) => Ok((root, level)),
+        None => Err(invalid_target(target))
+    }
+}
+
+/// Recognizes statements like `a.b.push(x)`, which append to an array held in a variable.
+fn push_into_path(expression: Token) -> Option<Lowered<ValueOrRoot>> {
+    let mut parts = expression.clone().into_inner();
+    let operand = parts.next().unwrap();
+    if parts.next().is_some() {
+        return None;
+    }
+    let mut inner: Vec<Token> = operand.into_inner().collect();
+    let invoke = inner.last().unwrap().clone().into_inner().next()?;
+    if invoke.as_rule() != Rule::methodInvoke {
+        return None;
+    }
+    let mut invoke = invoke.into_inner();
+    if invoke.next().unwrap().as_str() != "push" {
+        return None;
+    }
+    inner.pop();
+    let args: Lowered<Vec<Value>> = invoke.next().unwrap().tunify();
+    Some(both(path_of(inner.into_iter(), &expression), args).map(|((root, level), vals)| {
+        Either::Left(Root::Update{root, level, operation: Mut::Push(vals)})
+    }))
+}
+
+impl<'a> Tuna<Conditional> for Token<'a> {
+    fn tunify(self) -> Lowered<Conditional> {
+        match self.as_rule() {
+            Rule::conditional => {
+                let mut parts = self.into_inner();
+                let condition = parts.next().unwrap().tunify();
+                let (condition, body): (_, Vec<ValueOrRoot>) = both(condition, parts.next().unwrap().tunify())?;
+                Ok(Conditional {
+                    condition,
+                    body: into_roots(body)
+                })
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+/// Nested blocks only hold roots, so bare expressions become statements whose result is dropped.
+fn into_roots(body: Vec<ValueOrRoot>) -> Vec<Root> {
+    body.into_iter().map(|statement| match statement {
+        Either::Left(root) => root,
+        Either::Right(value) => match *value {
+            AnyValue::Call(call) => Root::Call(call),
+            value => Root::Discard(Box::new(value))
+        }
+    }).collect()
+}
+
+impl<'a> Tuna<Vec<ValueOrRoot>> for Token<'a> {
+    fn tunify(self) -> Lowered<Vec<ValueOrRoot>> {
+        match self.as_rule() {
+            Rule::scope => tunify_all(self.into_inner()),
+            _ => unreachable!()
+        }
+    }
+}
+
+impl<'a> Tuna<ir::Function<'a>> for Token<'a> {
+    fn tunify(self) -> Lowered<ir::Function<'a>> {
+        match self.as_rule() {
+            Rule::func => {
+                let mut name = None;
+                let mut visibility = Visibility::Private;
+                let mut doc: Vec<&str> = vec![];
+                let mut args = Ok(vec![]);
+                let mut returns = Ok(Schema::Any);
+                let mut body = Ok(vec![]);
+                let span = Span::of(&self);
+                for pair in self.into_inner() {
+                    match pair.as_rule() {
+                        Rule::doc => {
+                            let line = &pair.as_str()[3..];
+                            doc.push(line.strip_prefix(' ').unwrap_or(line).trim_end());
+                        },
+                        Rule::public => visibility = Visibility::Public,
+                        Rule::name => name = Some(pair.as_str()),
+                        Rule::params => args = pair.tunify(),
+                        Rule::schema => returns = pair.tunify(),
+                        Rule::scope => body = pair.tunify(),
+                        _ => unreachable!()
+                    }
+                }
+                let ((args, returns), body) = both(both(args, returns), body)?;
+                Ok(Function {
+                    name: name.unwrap(),
+                    visibility,
+                    doc: if doc.is_empty() { None } else { Some(doc.join("\n")) },
+                    args,
+                    returns,
+                    body,
+                    span
+                })
+            },
+            _ => unreachable!()
+        }
+    }
+}
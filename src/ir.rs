@@ -3,6 +3,9 @@
 
 use tuna_interpreter::data::{InterpreterType};
 use tuna_interpreter::schemas::{Schema};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use crate::error::Span;
 
 pub struct Conditional {
     pub condition: Value, 
@@ -24,8 +27,17 @@ pub enum Root {
     Branch(Vec<Conditional>),
     Save {val: Value, name: String},
     Update {root: Saved, level: Vec<Value>, operation: Mut},
-    ForEach {target: Value, body: Vec<Root>, arg: String},
+    /// Binds `arg` to each element of an array or key of an object, or to the
+    /// index/key when `value` is also bound to the element itself.
+    ForEach {target: Value, body: Vec<Root>, arg: String, value: Option<String>},
+    While {condition: Value, body: Vec<Root>},
+    /// Leaves the innermost loop.
+    Break,
+    /// Starts the next iteration of the innermost loop.
+    Continue,
     Call(Call),
+    /// An expression evaluated only for its side effects.
+    Discard(Value),
     Return(Option<Value>)
 }
 
@@ -40,7 +52,7 @@ pub struct Field {
     pub value: Value
 }
 pub struct Saved(pub String);
-type Value = Box<AnyValue>;
+pub type Value = Box<AnyValue>;
 pub enum AnyValue {
     Bool(bool),
     Object(Vec<Field>),
@@ -50,14 +62,69 @@ pub enum AnyValue {
     None,
     GetType(Value),
     Not(Value),
+    Negate(Value),
     BinaryOp {sign: Sign, left: Value, right: Value},
     Is {val: Value, typ: String},
-    RoleInstance {schema: Schema, data: Vec<Field>},
+    RoleInstance {name: String, data: Vec<Field>},
     Saved(String),
     Selection {root: Value, level: Vec<Value>},
     Keys(Value),
+    Len(Value),
+    Has {val: Value, key: Value},
+    /// One of the built in methods of arrays, such as `map` or `slice`.
+    ArrayMethod {method: ArrayMethod, target: Value, args: Vec<Value>},
     Array(Vec<Value>),
-    Call(Call)
+    Call(Call),
+    /// A function defined inline, which captures the variables it uses from
+    /// the scope it's defined in.
+    Lambda {args: Vec<(Schema, String)>, body: Vec<Root>}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ArrayMethod {
+    Map,
+    Filter,
+    Reduce,
+    Sort,
+    Find,
+    Any,
+    All,
+    Slice,
+    Concat,
+    Reverse,
+    IndexOf,
+    Flatten
+}
+
+impl ArrayMethod {
+    pub fn named(name: &str) -> Option<ArrayMethod> {
+        Some(match name {
+            "map" => ArrayMethod::Map,
+            "filter" => ArrayMethod::Filter,
+            "reduce" => ArrayMethod::Reduce,
+            "sort" => ArrayMethod::Sort,
+            "find" => ArrayMethod::Find,
+            "any" => ArrayMethod::Any,
+            "all" => ArrayMethod::All,
+            "slice" => ArrayMethod::Slice,
+            "concat" => ArrayMethod::Concat,
+            "reverse" => ArrayMethod::Reverse,
+            "index_of" => ArrayMethod::IndexOf,
+            "flatten" => ArrayMethod::Flatten,
+            _ => return None
+        })
+    }
+
+    /// How many arguments the method takes.
+    pub fn arity(&self) -> RangeInclusive<usize> {
+        match self {
+            ArrayMethod::Reverse | ArrayMethod::Flatten => 0..=0,
+            ArrayMethod::Sort => 0..=1,
+            ArrayMethod::Slice => 1..=2,
+            ArrayMethod::Reduce => 2..=2,
+            _ => 1..=1
+        }
+    }
 }
 
 pub enum Sign {
@@ -76,8 +143,23 @@ pub enum Sign {
 }
 
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Visibility {
+    /// Callable by clients, declared with `pub func`.
+    Public,
+    /// Not advertised to clients, though hosts may still run it directly.
+    Private
+}
+
 pub struct Function<'a> {
     pub name: &'a str,
+    pub visibility: Visibility,
+    /// The function's `///` comments, without the slashes.
+    pub doc: Option<String>,
     pub args: Vec<(Schema, String)>,
-    pub body: Vec<ValueOrRoot>
+    /// Schema every returned value must match, `Any` when undeclared.
+    pub returns: Schema,
+    pub body: Vec<ValueOrRoot>,
+    /// Where the function is defined, for errors found while lowering it.
+    pub span: Span
 }
\ No newline at end of file
@@ -3,267 +3,56 @@
 extern crate pest_derive;
 extern crate pest;
 
-use ir::*;
-use pest::{Parser, error::Error};
+use pest::Parser;
 use pest::iterators::{Pairs, Pair};
-use std::{collections::HashMap};
+use std::collections::HashMap;
 use tuna_interpreter::schemas::Schema;
 use tuna_interpreter::ops::Op;
-use std::str::FromStr;
+use frontend::{Tuna, Lowered};
+use error::{CompileError, Diagnostics};
+use ir::Visibility;
+use serde::{Deserialize, Serialize};
 
 pub mod ir;
 pub mod backend;
 pub mod frontend;
+pub mod error;
+pub mod artifact;
+pub mod disasm;
 mod scope;
+mod captures;
+mod optimizer;
+mod resolver;
+mod typecheck;
 
 #[derive(Parser)]
 #[grammar = "tuna.pest"]
 pub struct TunaParser;
 
+#[derive(Serialize, Deserialize)]
 pub struct Compiled {
     pub schemas: HashMap<String, Schema>, 
     pub stores: HashMap<String, Schema>,
     pub fns: HashMap<String, Vec<Op>>,
+    /// Lambdas, named after the function they're defined in. Only closures
+    /// can call them, so they aren't entry points like `fns`.
+    pub lambdas: HashMap<String, Vec<Op>>,
+    pub functions: HashMap<String, FunctionInfo>,
 }
 
-
-trait Tuna<T> {
-    fn tunify(self) -> T;
+/// What callers need to know about a function without looking at its ops.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub visibility: Visibility,
+    pub params: Vec<(String, Schema)>,
+    pub returns: Schema,
+    pub doc: Option<String>,
+    /// Names of the variables held in each heap slot, for disassembly.
+    #[serde(default)]
+    pub slots: Vec<Vec<String>>
 }
 
-type Token<'a> = Pair<'a, Rule>;
-
-impl<'a> Tuna<Vec<(Schema, String)>> for Token<'a> {
-    fn tunify(self) -> Vec<(Schema, String)> {
-        match self.as_rule() {
-            Rule::params => {
-                let mut v = vec![];
-                for param in self.into_inner() {        
-                    println!("PARAM {}", param.as_str());
-                    match param.as_rule() {
-                        Rule::name => v.push((Schema::Any, param.as_str().to_string())),
-                        _ => panic!("Unexpected: {}", param)
-                    };            
-                }
-                v
-            },
-            _ => unreachable!()
-        }
-    }
-}
-
-impl<'a> Tuna<Vec<Box<AnyValue>>> for Token<'a> {
-    fn tunify(self) -> Vec<Box<AnyValue>> {
-        let mut args = vec![];
-        match self.as_rule() {
-            Rule::args => {
-                for p in self.into_inner() {
-                    args.push(p.tunify());
-                }
-            },
-            _ => unreachable!()
-        };
-
-        args
-    }
-}
-
-impl<'a> Tuna<Call> for Token<'a> {
-    fn tunify(self) -> Call {
-        match self.as_rule() {
-            Rule::functionCall => {
-                let mut name = None;
-                let mut args = vec![];
-                for p in self.into_inner() {
-                    match p.as_rule() {
-                        Rule::name => name = Some(p.as_str()),
-                        Rule::args => {
-                            println!("arg {}", p.as_str());
-                            args = p.tunify();                            
-                        },
-                        _ => unreachable!()
-                    };
-                }
-                Call {
-                    function: name.unwrap().to_string(),
-                    args
-                }
-            },
-            _ => unreachable!()
-        }
-    }
-}
-
-impl<'a> Tuna<Box<AnyValue>> for Token<'a> {
-    fn tunify(self) -> Box<AnyValue> {
-        let val = match self.as_rule() {
-            Rule::expression => {
-                let mut body = None;
-                // let _prefix = None;
-                // let _methods = vec![];
-                for p in self.into_inner() {                                        
-                    match p.as_rule() {
-                        Rule::prefix|
-                        Rule::method|
-                        Rule::infix => {unreachable!()},
-                        Rule::literal => {
-                            let lit = p.into_inner().peek().unwrap();
-                            body = Some(match lit.as_rule() {
-                                Rule::object => {
-                                    let mut fields = vec![];
-                                    let mut name = None;
-                                    for field in lit.into_inner() {
-                                        match field.as_rule() {
-                                            Rule::name => name = Some(field.as_str().to_string()),
-                                            Rule::expression => {
-                                                fields.push(Field {
-                                                    key: name.unwrap(),
-                                                    value: field.tunify()
-                                                });
-                                                name = None;
-                                            },
-                                            _ => unreachable!()
-                                        };
-                                    }
-                                    AnyValue::Object(fields)
-                                },
-                                Rule::string => {
-                                    let full = lit.as_str();
-                                    let string = if full.len() > 2 {
-                                        unsafe {
-                                            full.get_unchecked(1..full.len() - 1)
-                                        }
-                                    } else {
-                                        ""
-                                    };
-                                    AnyValue::String(string.to_string())
-                                },
-                                Rule::boolean => AnyValue::Bool(lit.as_str() == "true"),
-                                Rule::num => AnyValue::Double(f64::from_str(lit.as_str()).unwrap()),
-                                Rule::none => AnyValue::None,
-                                Rule::array => {
-                                    let mut values = vec![];
-                                    for v in lit.into_inner() {
-                                        values.push(v.tunify());
-                                    }
-                                    AnyValue::Array(values)
-                                },
-                                _ => unreachable!()
-                            });
-                        },
-                        
-                        Rule::functionCall => body = Some(AnyValue::Call(p.tunify())),
-                        Rule::name => body = Some(AnyValue::Saved(p.as_str().to_string())),
-                        _ => unreachable!()
-                    };
-                }
-                body.unwrap()
-            },
-            _ => unreachable!()
-        };
-        Box::new(val)
-    }
-}
-
-impl<'a> Tuna<ValueOrRoot> for Token<'a> {
-    fn tunify(self) -> ValueOrRoot {
-        match self.as_rule() {
-            Rule::ret => {
-                let mut exp = None;
-                for i in self.into_inner() {
-                    match i.as_rule() {
-                        Rule::expression => exp = Some(i.tunify()),
-                        _ => unreachable!()
-                    };
-                }
-                Either::Left(Root::Return(exp))
-            },
-            Rule::var => {
-                let mut exp = None;
-                let mut name = None;
-                for i in self.into_inner() {
-                    match i.as_rule() {
-                        Rule::name => name = Some(i.as_str()),
-                        Rule::expression => exp = Some(i.tunify()),
-                        _ => unreachable!()
-                    };
-                }
-                Either::Left(
-                    Root::Save{val: exp.unwrap(), name: name.unwrap().to_string()}
-                )
-            },
-            // Rule::forLoop => {
-
-            // },
-            // Rule::ifs => {
-
-            // },
-            // Rule::assignment => roots.push(part.tunify()),
-            Rule::expression => Either::Right(self.tunify()),
-            _ => unreachable!()
-        }
-    }
-}
-
-impl<'a> Tuna<Vec<ValueOrRoot>> for Token<'a> {
-    fn tunify(self) -> Vec<ValueOrRoot> {
-        let mut roots = vec![];
-        match self.as_rule() {
-            Rule::scope => {
-                for part in self.into_inner() {
-                    match part.as_rule() {
-                        Rule::ret |
-                        Rule::var |
-                        Rule::forLoop |
-                        Rule::ifs |
-                        Rule::assignment => roots.push(part.tunify()),
-                        Rule::expression => roots.push(part.tunify()),
-                        _ => unreachable!()
-                    }
-                }
-            },
-            _ => unreachable!()
-        }
-        
-        roots
-    }
-}
-
-impl<'a> Tuna<ir::Function<'a>> for Pair<'a, Rule> {
-    fn tunify(self) -> ir::Function<'a> {
-        match self.as_rule() {
-            Rule::func => {
-                let pairs = self.into_inner();
-                let mut name = None;
-                let mut args = vec![];
-                let mut body = vec![];
-                for pair in pairs {
-                    match pair.as_rule() {
-                        Rule::name => {
-                            name = Some(pair.as_str());
-                            println!("NAME {}", name.unwrap());
-                        },
-                        Rule::params => {
-                            println!("PARAMS {}", pair.as_str());
-                            args.append(&mut pair.tunify());
-                        },
-                        Rule::scope => {
-                            println!("SCOPE {}", pair.as_str());
-                            body.append(&mut pair.tunify());
-                        },
-                        _ => panic!("Unexpected rule {}", pair)
-                    }
-                }                
-                Function {
-                    name: name.unwrap(),
-                    args,
-                    body
-                }
-            },
-            _ => panic!("Unexpected rule {}", self)
-        }
-    }
-}
 
 fn print_everything(p: Pair<Rule>) {
     println!("P {:?}", p);        
@@ -278,17 +67,40 @@ fn print_everything(p: Pair<Rule>) {
     }
 }
 
-pub fn compile(input: &str) -> Result<Compiled, Error<Rule>> {
-    let globals: Pairs<Rule> = TunaParser::parse(Rule::globals, input)?;
+pub fn compile(input: &str) -> Result<Compiled, Diagnostics> {
+    compile_file("<input>", input)
+}
+
+/// Compiles a program, attributing any diagnostics to `file`.
+pub fn compile_file(file: &str, input: &str) -> Result<Compiled, Diagnostics> {
+    compile_globals(input).map_err(|mut errors| {
+        for e in &mut errors {
+            e.span.file = file.to_string();
+        }
+        errors
+    })
+}
+
+fn compile_globals(input: &str) -> Result<Compiled, Diagnostics> {
+    let globals: Pairs<Rule> = TunaParser::parse(Rule::globals, input).map_err(CompileError::from)?;
     let mut funcs = HashMap::new();
     let mut stores = HashMap::new();
+    let mut schemas = HashMap::new();
+    let mut errors = vec![];
+    errors.append(&mut resolver::resolve(globals.clone()));
+    let program = globals.clone();
     for global in globals {
         
         for thing in global.into_inner() {
             match thing.as_rule() {
                 Rule::func => {
-                    let f: ir::Function = thing.tunify();
-                    funcs.insert(f.name.to_string(), f);
+                    let f: Lowered<ir::Function> = thing.tunify();
+                    match f {
+                        Ok(f) => {
+                            funcs.insert(f.name.to_string(), f);
+                        },
+                        Err(mut e) => errors.append(&mut e)
+                    };
                 },
                 Rule::globject => {
                     let mut name = None;
@@ -299,20 +111,73 @@ pub fn compile(input: &str) -> Result<Compiled, Error<Rule>> {
                         };
                     }
                     stores.insert(name.unwrap().to_string(), Schema::Any);
-                }
-                _ => panic!("Unexpected rule {}", thing)
+                },
+                Rule::typeDef => {
+                    let mut parts = thing.into_inner();
+                    let name = parts.next().unwrap().as_str().to_string();
+                    let schema: Lowered<Schema> = parts.next().unwrap().tunify();
+                    match schema {
+                        Ok(s) => {
+                            schemas.insert(name, s);
+                        },
+                        Err(mut e) => errors.append(&mut e)
+                    };
+                },
+                Rule::roleDef => {
+                    let mut parts = thing.into_inner();
+                    let name = parts.next().unwrap().as_str().to_string();
+                    let state: Lowered<Schema> = parts.next().unwrap().tunify();
+                    match state {
+                        Ok(s) => {
+                            schemas.insert(name.clone(), Schema::Role(name, vec![s]));
+                        },
+                        Err(mut e) => errors.append(&mut e)
+                    };
+                },
+                Rule::EOI => {},
+                _ => unreachable!()
             };
         }        
     }
+    errors.append(&mut typecheck::check(program, &schemas));
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
 
     let mut fns = HashMap::with_capacity(funcs.len());
+    let mut lambdas = HashMap::new();
+    let mut functions = HashMap::with_capacity(funcs.len());
     for (k, v) in funcs.drain() {
-        fns.insert(k, backend::to_ops(v));
+        let mut info = FunctionInfo {
+            name: k.clone(),
+            visibility: v.visibility,
+            params: v.args.iter().map(|(schema, name)| (name.clone(), schema.clone())).collect(),
+            returns: v.returns.clone(),
+            doc: v.doc.clone(),
+            slots: vec![]
+        };
+        let lowered = match backend::lower(v) {
+            Ok(lowered) => lowered,
+            Err(e) => {
+                errors.push(e);
+                continue
+            }
+        };
+        info.slots = lowered.slots;
+        functions.insert(k.clone(), info);
+        fns.insert(k, lowered.ops);
+        lambdas.extend(lowered.lambdas);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
     }
 
     Ok(Compiled {
-        schemas: HashMap::new(),
+        schemas,
         stores,
-        fns
+        fns,
+        lambdas,
+        functions
     })
-}
\ No newline at end of file
+}
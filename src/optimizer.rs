@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+use tuna_interpreter::ops::Op;
+use tuna_interpreter::data::{InterpreterType, Compare};
+use crate::backend::{Instr, Label};
+
+type Pass = fn(Vec<Instr>) -> Vec<Instr>;
+
+const PASSES: [Pass; 4] = [remove_noops, fold_constants, peephole, remove_dead_code];
+
+/// Runs every pass until none of them can shrink the code any further.
+/// Passes only ever replace instructions with fewer of them, so this ends.
+pub(crate) fn optimize(mut code: Vec<Instr>) -> Vec<Instr> {
+    loop {
+        let before = code.len();
+        for pass in PASSES.iter() {
+            code = pass(code);
+        }
+        if code.len() == before {
+            return code
+        }
+    }
+}
+
+fn remove_noops(code: Vec<Instr>) -> Vec<Instr> {
+    code.into_iter().filter(|instr| !matches!(instr, Instr::Op(Op::noop))).collect()
+}
+
+/// Evaluates ops whose operands are all literals. Anything that would fail
+/// is left alone so it still fails at runtime.
+fn fold_constants(code: Vec<Instr>) -> Vec<Instr> {
+    let mut out: Vec<Instr> = Vec::with_capacity(code.len());
+    for instr in code {
+        out.push(instr);
+        loop {
+            // Binary ops are tried last since their pattern also matches the unary ones.
+            let folded = match out.as_slice() {
+                [.., Instr::Op(Op::instantiate(InterpreterType::bool(b))), Instr::Op(Op::negatePrev)] => {
+                    Some((2, vec![Instr::Op(Op::instantiate(InterpreterType::bool(!b)))]))
+                },
+                [.., Instr::Op(Op::instantiate(value)), Instr::Op(Op::nNegate)] => {
+                    value.negate().ok().map(|result| (2, vec![Instr::Op(Op::instantiate(result))]))
+                },
+                [.., Instr::Op(Op::instantiate(InterpreterType::bool(b))), Instr::JumpIf(label)] => {
+                    Some((2, if *b { vec![Instr::Jump(*label)] } else { vec![] }))
+                },
+                [.., Instr::Op(Op::instantiate(left)), Instr::Op(Op::instantiate(right)), Instr::Op(op)] => {
+                    binary(op, left, right).map(|result| (3, vec![Instr::Op(Op::instantiate(result))]))
+                },
+                _ => None
+            };
+            match folded {
+                Some((replaced, mut with)) => {
+                    out.truncate(out.len() - replaced);
+                    out.append(&mut with);
+                },
+                None => break
+            };
+        }
+    }
+    out
+}
+
+/// Mirrors the interpreter, where `right` is on top of the stack.
+fn binary(op: &Op, left: &InterpreterType, right: &InterpreterType) -> Option<InterpreterType> {
+    let result = match op {
+        Op::plus => left.plus(right),
+        Op::nMinus => left.minus(right),
+        Op::nMult => left.multiply(right),
+        Op::nDivide => left.divide(right),
+        Op::equal => Ok(InterpreterType::bool(left.equals(right))),
+        Op::less => left.compare(right).map(|c| InterpreterType::bool(matches!(c, Compare::Less))),
+        Op::lesseq => left.compare(right).map(|c| InterpreterType::bool(!matches!(c, Compare::Greater))),
+        Op::boolAnd | Op::boolOr => match (left, right) {
+            (InterpreterType::bool(l), InterpreterType::bool(r)) => {
+                Ok(InterpreterType::bool(if matches!(op, Op::boolAnd) { *l && *r } else { *l || *r }))
+            },
+            _ => return None
+        },
+        _ => return None
+    };
+    result.ok()
+}
+
+/// Ops that always leave a bool on top of the stack.
+fn pushes_bool(op: &Op) -> bool {
+    matches!(op,
+        Op::equal | Op::less | Op::lesseq | Op::boolAnd | Op::boolOr | Op::negatePrev |
+        Op::isLastNone | Op::fieldExists | Op::enforceSchemaInstanceOnStack(_) |
+        Op::enforceSchemaInstanceOnHeap{..} | Op::stackTopMatches{..} | Op::toBool
+    )
+}
+
+/// Small rewrites of adjacent instructions:
+/// - a negated comparison tested by a branch, `equal; negatePrev; negatePrev`,
+///   tests the comparison directly
+/// - a jump to the label right after it is dropped
+fn peephole(code: Vec<Instr>) -> Vec<Instr> {
+    let mut out: Vec<Instr> = Vec::with_capacity(code.len());
+    for (i, instr) in code.iter().enumerate() {
+        match (out.as_slice(), instr) {
+            ([.., Instr::Op(op), Instr::Op(Op::negatePrev)], Instr::Op(Op::negatePrev)) if pushes_bool(op) => {
+                out.pop();
+                continue;
+            },
+            (_, Instr::Jump(label)) if falls_through_to(&code[i + 1..], *label) => continue,
+            _ => {}
+        };
+        out.push(instr.clone());
+    }
+    out
+}
+
+fn falls_through_to(rest: &[Instr], label: Label) -> bool {
+    rest.iter().take_while(|instr| matches!(instr, Instr::Label(_))).any(|instr| matches!(instr, Instr::Label(l) if *l == label))
+}
+
+/// Drops whatever follows a return, error or jump up to the next label
+/// something jumps to, along with labels nothing jumps to.
+fn remove_dead_code(code: Vec<Instr>) -> Vec<Instr> {
+    let targets: HashSet<Label> = code.iter().filter_map(|instr| match instr {
+        Instr::Jump(label) | Instr::JumpIf(label) => Some(*label),
+        _ => None
+    }).collect();
+    let mut out = Vec::with_capacity(code.len());
+    let mut reachable = true;
+    for instr in code {
+        match &instr {
+            Instr::Label(label) if targets.contains(label) => reachable = true,
+            Instr::Label(_) => continue,
+            _ if !reachable => continue,
+            Instr::Jump(_) | Instr::Op(Op::returnStackTop) | Instr::Op(Op::returnVoid) | Instr::Op(Op::raiseError(_)) => {
+                reachable = false
+            },
+            _ => {}
+        };
+        out.push(instr);
+    }
+    out
+}
@@ -0,0 +1,307 @@
+use pest::iterators::Pairs;
+use crate::Rule;
+use crate::error::{CompileError, Diagnostics, ErrorCode};
+use crate::frontend::{Token, is_builtin_method};
+use crate::scope::{ScopeMap, Entity};
+
+/// Checks that every name in a program refers to something it may be used as,
+/// reporting undefined and duplicate names, bad calls and writes to constants.
+pub(crate) fn resolve(globals: Pairs<Rule>) -> Diagnostics {
+    let things: Vec<Token> = globals.flat_map(|g| g.into_inner()).collect();
+    let mut resolver = Resolver {
+        scope: ScopeMap::new(),
+        loops: 0,
+        errors: vec![]
+    };
+    for thing in &things {
+        resolver.declare(thing.clone());
+    }
+    for thing in things {
+        resolver.global(thing);
+    }
+    resolver.errors
+}
+
+struct Resolver {
+    scope: ScopeMap,
+    /// How many loops enclose the statement being resolved.
+    loops: usize,
+    errors: Diagnostics
+}
+
+impl Resolver {
+    fn error(&mut self, code: ErrorCode, at: &Token, message: String) {
+        self.errors.push(CompileError::at(code, at, message));
+    }
+
+    fn define(&mut self, name: Token, entity: Entity) {
+        if let Err(message) = self.scope.add(name.as_str().to_string(), entity) {
+            self.error(ErrorCode::DuplicateDefinition, &name, message);
+        }
+    }
+
+    fn declare(&mut self, thing: Token) {
+        let entity = match thing.as_rule() {
+            Rule::func => {
+                let params = thing.clone().into_inner().find(|p| p.as_rule() == Rule::params).unwrap();
+                Entity::Func{arity: params.into_inner().filter(|p| p.as_rule() == Rule::name).count()}
+            },
+            Rule::globject => Entity::GlobalState,
+            Rule::typeDef => Entity::Type,
+            Rule::roleDef => Entity::Role,
+            _ => return
+        };
+        // A function's name is the one just before its parameters.
+        let name = thing.into_inner().filter(|p| p.as_rule() == Rule::name).last().unwrap();
+        self.define(name, entity);
+    }
+
+    fn global(&mut self, thing: Token) {
+        match thing.as_rule() {
+            Rule::func => {
+                self.scope.push();
+                for part in thing.into_inner() {
+                    match part.as_rule() {
+                        Rule::params => for param in part.into_inner() {
+                            match param.as_rule() {
+                                Rule::name => self.define(param, Entity::Var),
+                                _ => self.types(param)
+                            };
+                        },
+                        Rule::schema => self.types(part),
+                        // Parameters and the top of the body share a scope.
+                        Rule::scope => for statement in part.into_inner() {
+                            self.statement(statement);
+                        },
+                        _ => {}
+                    };
+                }
+                self.scope.pop();
+            },
+            Rule::typeDef | Rule::roleDef => for part in thing.into_inner().skip(1) {
+                self.types(part);
+            },
+            _ => {}
+        };
+    }
+
+    fn types(&mut self, schema: Token) {
+        for body in schema.into_inner().flatten().filter(|p| p.as_rule() == Rule::typeBody) {
+            let name = body.into_inner().next().unwrap();
+            if name.as_rule() != Rule::name {
+                continue;
+            }
+            match self.scope.get(name.as_str()) {
+                Some(Entity::Type) | Some(Entity::Role) => {},
+                _ => self.error(ErrorCode::UnknownType, &name, format!("Unknown type {}", name.as_str()))
+            };
+        }
+    }
+
+    fn block(&mut self, scope: Token) {
+        self.scope.push();
+        for statement in scope.into_inner() {
+            self.statement(statement);
+        }
+        self.scope.pop();
+    }
+
+    fn statement(&mut self, statement: Token) {
+        match statement.as_rule() {
+            Rule::var => {
+                let mut parts = statement.into_inner();
+                let entity = match parts.next().unwrap().as_rule() {
+                    Rule::constant => Entity::Const,
+                    _ => Entity::Var
+                };
+                let name = parts.next().unwrap();
+                self.expression(parts.next().unwrap());
+                self.define(name, entity);
+            },
+            Rule::assignment => {
+                let mut parts = statement.into_inner();
+                let target = parts.next().unwrap();
+                self.expression(parts.next().unwrap());
+                self.mutated(target.clone());
+                self.expression(target);
+            },
+            Rule::delete => {
+                let target = statement.into_inner().next().unwrap();
+                self.mutated(target.clone());
+                self.expression(target);
+            },
+            Rule::expression => {
+                if is_push(&statement) {
+                    self.mutated(statement.clone());
+                }
+                self.expression(statement);
+            },
+            Rule::ret => for value in statement.into_inner() {
+                self.expression(value);
+            },
+            Rule::forLoop => {
+                let mut names = vec![];
+                let mut body = None;
+                for part in statement.into_inner() {
+                    match part.as_rule() {
+                        Rule::name => names.push(part),
+                        Rule::expression => self.expression(part),
+                        _ => body = Some(part)
+                    };
+                }
+                self.scope.push();
+                for name in names {
+                    self.define(name, Entity::Var);
+                }
+                self.loop_body(body.unwrap());
+                self.scope.pop();
+            },
+            Rule::whileLoop => {
+                let mut parts = statement.into_inner();
+                self.expression(parts.next().unwrap());
+                self.loop_body(parts.next().unwrap());
+            },
+            Rule::breakLoop | Rule::continueLoop => if self.loops == 0 {
+                let message = format!("{} can only be used inside a loop", statement.as_str());
+                self.error(ErrorCode::OutsideLoop, &statement, message);
+            },
+            Rule::ifs => for branch in statement.into_inner() {
+                self.branch(branch);
+            },
+            _ => unreachable!()
+        };
+    }
+
+    fn loop_body(&mut self, body: Token) {
+        self.loops += 1;
+        self.block(body);
+        self.loops -= 1;
+    }
+
+    fn branch(&mut self, branch: Token) {
+        match branch.as_rule() {
+            Rule::conditional => {
+                let mut parts = branch.into_inner();
+                self.expression(parts.next().unwrap());
+                self.block(parts.next().unwrap());
+            },
+            Rule::elif => self.branch(branch.into_inner().next().unwrap()),
+            Rule::otherwise => self.block(branch.into_inner().next().unwrap()),
+            _ => unreachable!()
+        };
+    }
+
+    /// Reports writes through anything but a variable; the shape of the target
+    /// is left to lowering, which knows what a valid target looks like.
+    fn mutated(&mut self, target: Token) {
+        if let Some(root) = root_of(&target) {
+            match self.scope.get(root.as_str()) {
+                Some(Entity::Var) | None => {},
+                Some(Entity::Const) => self.error(ErrorCode::AssignToConst, &root, format!("Cannot assign to constant {}", root.as_str())),
+                Some(Entity::Func{..}) => self.error(ErrorCode::InvalidTarget, &root, format!("Cannot assign to function {}", root.as_str())),
+                Some(_) => self.error(ErrorCode::InvalidTarget, &root, format!("Cannot assign to {}, which is not a variable", root.as_str()))
+            };
+        }
+    }
+
+    fn expression(&mut self, value: Token) {
+        match value.as_rule() {
+            Rule::name => match self.scope.get(value.as_str()) {
+                Some(Entity::Var) | Some(Entity::Const) | Some(Entity::Func{..}) => {},
+                Some(_) => self.error(ErrorCode::UndefinedName, &value, format!("{} is not a variable", value.as_str())),
+                None => self.error(ErrorCode::UndefinedName, &value, format!("Undefined variable {}", value.as_str()))
+            },
+            Rule::functionCall => {
+                let mut parts = value.into_inner();
+                let name = parts.next().unwrap();
+                let args = parts.next().unwrap();
+                self.call(name, args.clone().into_inner().count(), "function");
+                self.expression(args);
+            },
+            Rule::methodInvoke => {
+                let mut parts = value.into_inner();
+                let name = parts.next().unwrap();
+                let args = parts.next().unwrap();
+                if !is_builtin_method(name.as_str()) {
+                    self.call(name, args.clone().into_inner().count() + 1, "method");
+                } else if let Some(Entity::Func{..}) = self.scope.get(name.as_str()) {
+                    let message = format!("{0}() is a built in method, so it can't call function {0}; call {0}(x, ...) instead", name.as_str());
+                    self.error(ErrorCode::ShadowedFunction, &name, message);
+                }
+                self.expression(args);
+            },
+            Rule::roleInstance => {
+                let mut parts = value.into_inner();
+                let name = parts.next().unwrap();
+                match self.scope.get(name.as_str()) {
+                    Some(Entity::Role) => {},
+                    _ => self.error(ErrorCode::UnknownType, &name, format!("Unknown role {}", name.as_str()))
+                };
+                self.expression(parts.next().unwrap());
+            },
+            // Object keys and accessed fields are not variables.
+            Rule::object => for field in value.into_inner().filter(|p| p.as_rule() == Rule::expression) {
+                self.expression(field);
+            },
+            Rule::lambda => {
+                // Loops outside the lambda can't be left from inside it.
+                let loops = std::mem::replace(&mut self.loops, 0);
+                self.scope.push();
+                for part in value.into_inner() {
+                    match part.as_rule() {
+                        Rule::params => for param in part.into_inner() {
+                            match param.as_rule() {
+                                Rule::name => self.define(param, Entity::Var),
+                                _ => self.types(param)
+                            };
+                        },
+                        // Like functions, parameters and the top of the body share a scope.
+                        Rule::scope => for statement in part.into_inner() {
+                            self.statement(statement);
+                        },
+                        _ => self.expression(part)
+                    };
+                }
+                self.scope.pop();
+                self.loops = loops;
+            },
+            Rule::fieldAccess => {},
+            _ => for inner in value.into_inner() {
+                self.expression(inner);
+            }
+        };
+    }
+
+    fn call(&mut self, name: Token, args: usize, kind: &str) {
+        match self.scope.get(name.as_str()) {
+            Some(Entity::Func{arity}) if *arity != args => {
+                let message = format!("{}() takes {} argument(s) but {} were given", name.as_str(), arity, args);
+                self.error(ErrorCode::WrongArity, &name, message);
+            },
+            // Variables may hold functions, which are checked when they're called.
+            Some(Entity::Func{..}) | Some(Entity::Var) | Some(Entity::Const) => {},
+            _ => self.error(ErrorCode::UnknownFunction, &name, format!("Unknown {} {}", kind, name.as_str()))
+        };
+    }
+}
+
+/// The variable at the start of an expression like `a.b[c]`, if there is one.
+pub(crate) fn root_of<'a>(target: &Token<'a>) -> Option<Token<'a>> {
+    target.clone().into_inner().next()
+        .filter(|operand| operand.as_rule() == Rule::operand)
+        .and_then(|operand| operand.into_inner().next())
+        .filter(|root| root.as_rule() == Rule::name)
+}
+
+/// Whether a statement is a call to `push`, which appends to the array it's called on.
+pub(crate) fn is_push(statement: &Token) -> bool {
+    let mut parts = statement.clone().into_inner();
+    let operand = match (parts.next(), parts.next()) {
+        (Some(operand), None) if operand.as_rule() == Rule::operand => operand,
+        _ => return false
+    };
+    match operand.into_inner().last().and_then(|m| m.into_inner().next()) {
+        Some(invoke) if invoke.as_rule() == Rule::methodInvoke => invoke.into_inner().next().unwrap().as_str() == "push",
+        _ => false
+    }
+}
@@ -1,51 +1,54 @@
-use tuna_interpreter::schemas::{Schema};
-use std::collections::{HashMap};
+use std::collections::HashMap;
 
 pub enum Entity {
-    Func,
+    Func{arity: usize},
     GlobalState,
-    Type(Schema),
+    Type,
+    Role,
     Const,
     Var
 }
 
+/// What each name in scope refers to. Inner scopes may shadow outer names,
+/// but a name can only be defined once per scope.
 pub struct ScopeMap {
-    lookup: HashMap<String, Entity>,
-    stack: Vec<Vec<String>>
+    stack: Vec<HashMap<String, Entity>>
 }
 
 impl ScopeMap {
     pub fn new() -> ScopeMap {
         ScopeMap {
-            lookup: HashMap::new(),
-            stack: vec![vec![]]
+            stack: vec![HashMap::new()]
         }
     }
 
     pub fn add(&mut self, name: String, e: Entity) -> Result<(), String> {
-        match self.lookup.insert(name.clone(), e) {
-            Some(_other) => return Err(format!("{} collides with something in scope.", name)),
-            _ => {}
-        };
-        self.stack.last_mut().unwrap().push(name);
+        let current = self.stack.last_mut().unwrap();
+        if current.contains_key(&name) {
+            return Err(format!("{} collides with something in scope.", name));
+        }
+        current.insert(name, e);
         Ok(())
     }
 
+    pub fn get(&self, name: &str) -> Option<&Entity> {
+        self.stack.iter().rev().find_map(|names| names.get(name))
+    }
+
     pub fn pop(&mut self) {
-        let remove = self.stack.pop().unwrap();
-        for thing in remove {
-            self.lookup.remove(&thing);
-        }
+        self.stack.pop();
     }
 
     pub fn push(&mut self) {
-        self.stack.push(vec![]);
+        self.stack.push(HashMap::new());
     }
 }
 
 pub struct ScopeSizer {
     lookup: HashMap<String, usize>,
-    stack: Vec<Vec<String>>
+    stack: Vec<Vec<String>>,
+    /// Every name that has occupied each heap slot, for debugging output.
+    pub slots: Vec<Vec<String>>
 }
 
 
@@ -53,18 +56,35 @@ impl ScopeSizer {
     pub fn new() -> ScopeSizer {
         ScopeSizer {
             lookup: HashMap::new(),
-            stack: vec![vec![]]
+            stack: vec![vec![]],
+            slots: vec![]
         }
     }
 
+    /// Whether `name` is a variable rather than a function.
+    pub(crate) fn is_local(&self, name: &str) -> bool {
+        self.lookup.contains_key(name)
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.stack.iter().map(|names| names.len()).sum()
+    }
+
     pub fn add(&mut self, name: String) -> usize {
-        let val = self.lookup.len();
+        let val = self.size();
         self.lookup.insert(name.clone(), val);
+        if self.slots.len() <= val {
+            self.slots.resize(val + 1, vec![]);
+        }
+        if !self.slots[val].contains(&name) {
+            self.slots[val].push(name.clone());
+        }
         self.stack.last_mut().unwrap().push(name);
         val
     }
-    pub fn get(& self, name: &String) -> u64 {
-        *self.lookup.get(name).unwrap() as u64
+    /// The heap slot of a variable, or `None` for anything else.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.lookup.get(name).map(|slot| *slot as u64)
     }
 
     pub fn pop(&mut self) -> u64 {
@@ -72,10 +92,20 @@ impl ScopeSizer {
         for thing in &remove {
             self.lookup.remove(thing);
         }
+        // Names shadowed by the popped scope refer to their outer slots again.
+        let mut slot = 0;
+        for names in &self.stack {
+            for name in names {
+                if remove.contains(name) {
+                    self.lookup.insert(name.clone(), slot);
+                }
+                slot += 1;
+            }
+        }
         remove.len() as u64
     }
 
     pub fn push(&mut self) {
         self.stack.push(vec![]);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,626 @@
+use pest::iterators::Pairs;
+use std::collections::{HashMap, HashSet};
+use tuna_interpreter::schemas::{Schema, ObjSchema};
+use crate::Rule;
+use crate::ir::{ArrayMethod, Sign};
+use crate::error::{CompileError, Diagnostics, ErrorCode};
+use crate::frontend::{Token, Tuna, Climber, Operand, climb};
+use crate::resolver::{root_of, is_push};
+
+/// Infers a schema for each expression and reports operations that would always fail at runtime.
+/// Anything inference can't pin down is `Any`, which is compatible with everything.
+pub(crate) fn check(globals: Pairs<Rule>, schemas: &HashMap<String, Schema>) -> Diagnostics {
+    let things: Vec<Token> = globals.flat_map(|g| g.into_inner()).filter(|t| t.as_rule() == Rule::func).collect();
+    let mut checker = Checker {
+        schemas,
+        functions: HashMap::new(),
+        returns: Schema::Any,
+        public: false,
+        env: vec![],
+        mutated: HashSet::new(),
+        errors: vec![]
+    };
+    for func in &things {
+        let (name, signature) = signature(func.clone());
+        checker.functions.insert(name, signature);
+    }
+    for func in things {
+        checker.function(func);
+    }
+    checker.errors
+}
+
+#[derive(Clone)]
+struct Signature {
+    params: Vec<(String, Schema)>,
+    returns: Schema,
+    public: bool
+}
+
+/// A function's name, its parameters' schemas and its return schema.
+fn signature(func: Token) -> (String, Signature) {
+    let name = func.clone().into_inner().filter(|p| p.as_rule() == Rule::name).last().unwrap().as_str().to_string();
+    let mut params: Vec<(String, Schema)> = vec![];
+    let mut returns = Schema::Any;
+    let mut public = false;
+    for part in func.into_inner() {
+        match part.as_rule() {
+            Rule::params => params = parameters(part),
+            Rule::schema => returns = part.tunify().unwrap_or(Schema::Any),
+            Rule::public => public = true,
+            _ => {}
+        };
+    }
+    (name, Signature {params, returns, public})
+}
+
+fn parameters(params: Token) -> Vec<(String, Schema)> {
+    let mut parsed: Vec<(String, Schema)> = vec![];
+    for param in params.into_inner() {
+        match param.as_rule() {
+            Rule::name => parsed.push((param.as_str().to_string(), Schema::Any)),
+            _ => parsed.last_mut().unwrap().1 = param.tunify().unwrap_or(Schema::Any)
+        };
+    }
+    parsed
+}
+
+/// What the runtime can do with a value of some schema.
+#[derive(PartialEq, Clone, Copy)]
+enum Kind {
+    Int,
+    Double,
+    Str,
+    Bool,
+    Other,
+    Unknown
+}
+
+impl Kind {
+    fn is_number(self) -> bool {
+        self == Kind::Int || self == Kind::Double
+    }
+}
+
+struct Checker<'s> {
+    schemas: &'s HashMap<String, Schema>,
+    functions: HashMap<String, Signature>,
+    /// The declared return schema of the function being checked.
+    returns: Schema,
+    /// Whether the function being checked responds to clients, so can't return functions.
+    public: bool,
+    env: Vec<HashMap<String, Schema>>,
+    /// Variables written to somewhere in the current function, whose schema can change.
+    mutated: HashSet<String>,
+    errors: Diagnostics
+}
+
+impl<'a, 's> Climber<'a> for Checker<'s> {
+    type Output = Schema;
+
+    fn operand(&mut self, operand: Token<'a>) -> Schema {
+        Checker::operand(self, operand)
+    }
+
+    fn not(&mut self, not: Token<'a>, operand: Schema) -> Schema {
+        self.expect(&not, &operand, &Schema::bool, "not");
+        Schema::bool
+    }
+
+    fn binary(&mut self, infix: Token<'a>, sign: Sign, left: Schema, right: Schema) -> Schema {
+        Checker::binary(self, &infix, sign, left, right)
+    }
+}
+
+impl<'s> Checker<'s> {
+    fn error(&mut self, at: &Token, message: String) {
+        self.errors.push(CompileError::at(ErrorCode::TypeMismatch, at, message));
+    }
+
+    fn define(&mut self, name: &str, schema: Schema) {
+        let schema = if self.mutated.contains(name) { Schema::Any } else { schema };
+        self.env.last_mut().unwrap().insert(name.to_string(), schema);
+    }
+
+    fn local(&self, name: &str) -> Option<Schema> {
+        self.env.iter().rev().find_map(|names| names.get(name)).cloned()
+    }
+
+    fn lookup(&self, name: &str) -> Schema {
+        match self.local(name) {
+            Some(schema) => schema,
+            None if self.functions.contains_key(name) => Schema::Function,
+            None => Schema::Any
+        }
+    }
+
+    /// Whether a value of this schema is or holds a function.
+    fn holds_function(&self, schema: &Schema) -> bool {
+        match self.expand(schema.clone()) {
+            Schema::Function => true,
+            Schema::Object(fields) => fields.0.values().any(|f| self.holds_function(f)),
+            Schema::Array(inner) | Schema::Map(inner) => self.holds_function(&inner[0]),
+            _ => false
+        }
+    }
+
+    /// Follows type aliases, leaving roles as references to their name.
+    fn expand(&self, schema: Schema) -> Schema {
+        let mut schema = schema;
+        for _ in 0..self.schemas.len() + 1 {
+            schema = match schema {
+                Schema::TypeAlias(name) => match self.schemas.get(&name) {
+                    Some(Schema::Role(..)) => return Schema::TypeAlias(name),
+                    Some(aliased) => aliased.clone(),
+                    None => return Schema::Any
+                },
+                other => return other
+            };
+        }
+        Schema::Any
+    }
+
+    fn kind(&self, schema: &Schema) -> Kind {
+        match self.expand(schema.clone()) {
+            Schema::int => Kind::Int,
+            Schema::double => Kind::Double,
+            Schema::string => Kind::Str,
+            Schema::bool => Kind::Bool,
+            Schema::Any | Schema::Union(_) => Kind::Unknown,
+            _ => Kind::Other
+        }
+    }
+
+    /// Whether a value of schema `actual` could be accepted where `expected` is required.
+    fn compatible(&self, actual: &Schema, expected: &Schema) -> bool {
+        match (self.expand(actual.clone()), self.expand(expected.clone())) {
+            (Schema::Any, _) | (_, Schema::Any) => true,
+            (Schema::Union(options), expected) => options.iter().any(|o| self.compatible(o, &expected)),
+            (actual, Schema::Union(options)) => options.iter().any(|o| self.compatible(&actual, o)),
+            (Schema::int, Schema::double) | (Schema::none, Schema::none) | (Schema::Function, Schema::Function) => true,
+            (Schema::Array(a), Schema::Array(e)) => self.compatible(&a[0], &e[0]),
+            (Schema::Object(a), Schema::Map(e)) => a.0.values().all(|v| self.compatible(v, &e[0])),
+            (Schema::Object(a), Schema::Object(e)) => {
+                a.0.keys().all(|k| e.0.contains_key(k)) && e.0.iter().all(|(k, e)| match a.0.get(k) {
+                    Some(a) => self.compatible(a, e),
+                    None => e.is_optional()
+                })
+            },
+            (Schema::TypeAlias(a), Schema::TypeAlias(e)) => a == e,
+            (actual, expected) => {
+                let (a, e) = (self.kind(&actual), self.kind(&expected));
+                a != Kind::Other && a == e
+            }
+        }
+    }
+
+    fn describe(&self, schema: &Schema) -> String {
+        match schema {
+            Schema::Object(fields) => {
+                let mut fields: Vec<String> = fields.0.iter().map(|(k, v)| format!("{}: {}", k, self.describe(v))).collect();
+                fields.sort();
+                format!("{{{}}}", fields.join(", "))
+            },
+            Schema::Role(name, _) | Schema::TypeAlias(name) => name.to_string(),
+            Schema::Array(inner) => format!("{}[]", self.describe(&inner[0])),
+            Schema::Map(inner) => format!("map of {}", self.describe(&inner[0])),
+            Schema::Union(options) => options.iter().map(|o| self.describe(o)).collect::<Vec<String>>().join(" or "),
+            Schema::double => "double".to_string(),
+            Schema::int => "int".to_string(),
+            Schema::string => "string".to_string(),
+            Schema::bool => "bool".to_string(),
+            Schema::Any => "any".to_string(),
+            Schema::none => "none".to_string(),
+            Schema::Function => "function".to_string()
+        }
+    }
+
+    fn expect(&mut self, at: &Token, actual: &Schema, expected: &Schema, what: &str) {
+        if !self.compatible(actual, expected) {
+            let message = format!("Expected {} for {} but found {}", self.describe(expected), what, self.describe(actual));
+            self.error(at, message);
+        }
+    }
+
+    fn function(&mut self, func: Token) {
+        let (_, Signature {params, returns, public}) = signature(func.clone());
+        self.returns = returns;
+        self.public = public;
+        self.mutated = func.clone().into_inner().flatten().filter_map(|p| match p.as_rule() {
+            Rule::assignment | Rule::delete => root_of(&p.into_inner().next().unwrap()),
+            Rule::expression if is_push(&p) => root_of(&p),
+            _ => None
+        }).map(|root| root.as_str().to_string()).collect();
+        self.env.push(HashMap::new());
+        for (name, schema) in params {
+            self.define(&name, schema);
+        }
+        for statement in func.into_inner().find(|p| p.as_rule() == Rule::scope).unwrap().into_inner() {
+            self.statement(statement);
+        }
+        self.env.pop();
+    }
+
+    fn block(&mut self, scope: Token) {
+        self.env.push(HashMap::new());
+        for statement in scope.into_inner() {
+            self.statement(statement);
+        }
+        self.env.pop();
+    }
+
+    fn statement(&mut self, statement: Token) {
+        match statement.as_rule() {
+            Rule::var => {
+                let mut parts = statement.into_inner().skip(1);
+                let name = parts.next().unwrap();
+                let schema = self.expression(parts.next().unwrap());
+                self.define(name.as_str(), schema);
+            },
+            // Targets are checked by lowering; only the assigned value is an expression.
+            Rule::assignment => {
+                self.expression(statement.into_inner().nth(1).unwrap());
+            },
+            Rule::delete => {},
+            Rule::expression => {
+                self.expression(statement);
+            },
+            Rule::ret => {
+                let returned = match statement.clone().into_inner().next() {
+                    Some(value) => self.expression(value),
+                    None => Schema::none
+                };
+                let returns = self.returns.clone();
+                self.expect(&statement, &returned, &returns, "the return value");
+                if self.public && self.holds_function(&returned) {
+                    self.error(&statement, "pub functions respond with JSON, so can't return functions".to_string());
+                }
+            },
+            Rule::forLoop => {
+                let mut names = vec![];
+                let mut body = None;
+                let mut target = Schema::Any;
+                for part in statement.into_inner() {
+                    match part.as_rule() {
+                        Rule::name => names.push(part.as_str().to_string()),
+                        Rule::expression => {
+                            target = self.expression(part.clone());
+                            if self.kind(&target) != Kind::Unknown && !matches!(self.expand(target.clone()), Schema::Array(_) | Schema::Object(_) | Schema::Map(_)) {
+                                let message = format!("Cannot loop over {}", self.describe(&target));
+                                self.error(&part, message);
+                            }
+                        },
+                        _ => body = Some(part)
+                    };
+                }
+                // Arrays yield elements and objects yield keys, or index/key and value pairs.
+                let (key, value) = match self.expand(target) {
+                    Schema::Array(inner) => (Schema::int, inner[0].clone()),
+                    Schema::Map(inner) => (Schema::string, inner[0].clone()),
+                    Schema::Object(_) => (Schema::string, Schema::Any),
+                    _ => (Schema::Any, Schema::Any)
+                };
+                let single = match key {
+                    Schema::int => value.clone(),
+                    ref other => other.clone()
+                };
+                self.env.push(HashMap::new());
+                match names.as_slice() {
+                    [element] => self.define(element, single),
+                    [index, element] => {
+                        self.define(index, key);
+                        self.define(element, value);
+                    },
+                    _ => unreachable!()
+                };
+                self.block(body.unwrap());
+                self.env.pop();
+            },
+            Rule::whileLoop => {
+                let mut parts = statement.into_inner();
+                let condition = parts.next().unwrap();
+                let schema = self.expression(condition.clone());
+                self.expect(&condition, &schema, &Schema::bool, "a condition");
+                self.block(parts.next().unwrap());
+            },
+            Rule::breakLoop | Rule::continueLoop => {},
+            Rule::ifs => for branch in statement.into_inner() {
+                self.branch(branch);
+            },
+            _ => unreachable!()
+        };
+    }
+
+    fn branch(&mut self, branch: Token) {
+        match branch.as_rule() {
+            Rule::conditional => {
+                let mut parts = branch.into_inner();
+                let condition = parts.next().unwrap();
+                let schema = self.expression(condition.clone());
+                self.expect(&condition, &schema, &Schema::bool, "a condition");
+                self.block(parts.next().unwrap());
+            },
+            Rule::elif => self.branch(branch.into_inner().next().unwrap()),
+            Rule::otherwise => self.block(branch.into_inner().next().unwrap()),
+            _ => unreachable!()
+        };
+    }
+
+    fn expression(&mut self, expression: Token) -> Schema {
+        climb(self, &mut expression.into_inner().peekable(), 0)
+    }
+
+    fn binary(&mut self, infix: &Token, sign: Sign, left: Schema, right: Schema) -> Schema {
+        let (l, r) = (self.kind(&left), self.kind(&right));
+        let numeric = |k: Kind| k.is_number() || k == Kind::Unknown;
+        let (valid, result) = match sign {
+            Sign::Eq | Sign::Neq => (true, Schema::bool),
+            Sign::And | Sign::Or => (
+                (l == Kind::Bool || l == Kind::Unknown) && (r == Kind::Bool || r == Kind::Unknown),
+                Schema::bool
+            ),
+            Sign::L | Sign::G | Sign::Leq | Sign::Geq => (numeric(l) && numeric(r), Schema::bool),
+            Sign::Plus => {
+                let addable = |k: Kind| numeric(k) || k == Kind::Str;
+                (addable(l) && addable(r), match (l, r) {
+                    (Kind::Unknown, _) | (_, Kind::Unknown) => Schema::Any,
+                    (Kind::Str, _) | (_, Kind::Str) => Schema::string,
+                    (Kind::Int, Kind::Int) => Schema::int,
+                    _ => Schema::double
+                })
+            },
+            Sign::Minus | Sign::Mult | Sign::Div => (numeric(l) && numeric(r), match (l, r) {
+                (Kind::Int, Kind::Int) => Schema::int,
+                (Kind::Double, _) | (_, Kind::Double) => Schema::double,
+                _ => Schema::Any
+            })
+        };
+        if !valid {
+            let message = format!("Cannot apply {} to {} and {}", infix.as_str().trim(), self.describe(&left), self.describe(&right));
+            self.error(infix, message);
+        }
+        result
+    }
+
+    fn operand(&mut self, operand: Token) -> Schema {
+        let operand = Operand::of(operand);
+        let part = operand.body;
+        let mut schema = match part.as_rule() {
+            Rule::expression => self.expression(part),
+            Rule::literal => self.literal(part.into_inner().next().unwrap()),
+            Rule::name => self.lookup(part.as_str()),
+            Rule::lambda => self.lambda(part),
+            Rule::functionCall => {
+                let mut parts = part.into_inner();
+                let name = parts.next().unwrap();
+                let args = parts.next().unwrap().into_inner().map(|a| (a.clone(), self.expression(a))).collect();
+                self.call(&name, args)
+            },
+            Rule::roleInstance => {
+                let mut parts = part.into_inner();
+                let name = parts.next().unwrap();
+                let data = parts.next().unwrap();
+                let given = self.literal(data.clone());
+                if self.holds_function(&given) {
+                    self.error(&data, format!("Role {} cannot hold functions", name.as_str()));
+                }
+                if let Some(Schema::Role(_, state)) = self.schemas.get(name.as_str()) {
+                    let state = state[0].clone();
+                    self.expect(&data, &given, &state, &format!("role {}", name.as_str()));
+                }
+                Schema::TypeAlias(name.as_str().to_string())
+            },
+            _ => unreachable!()
+        };
+        for m in operand.methods {
+            schema = self.method(schema, m);
+        }
+        for minus in operand.negations {
+            if !matches!(self.kind(&schema), Kind::Int | Kind::Double | Kind::Unknown) {
+                let message = format!("Cannot negate {}", self.describe(&schema));
+                self.error(&minus, message);
+                schema = Schema::Any;
+            }
+        }
+        schema
+    }
+
+    fn literal(&mut self, literal: Token) -> Schema {
+        match literal.as_rule() {
+            Rule::object => {
+                let mut fields = HashMap::new();
+                let mut parts = literal.into_inner();
+                while let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                    let schema = self.expression(value);
+                    fields.insert(name.as_str().to_string(), schema);
+                }
+                Schema::Object(ObjSchema(fields))
+            },
+            Rule::array => {
+                let elements: Vec<Schema> = literal.into_inner().map(|e| self.expression(e)).collect();
+                let described: HashSet<String> = elements.iter().map(|e| self.describe(e)).collect();
+                match (elements.into_iter().next(), described.len()) {
+                    (Some(element), 1) => Schema::Array(vec![element]),
+                    _ => Schema::Array(vec![Schema::Any])
+                }
+            },
+            Rule::string => Schema::string,
+            Rule::boolean => Schema::bool,
+            Rule::none => Schema::none,
+            Rule::num => match literal.into_inner().next().unwrap().into_inner().next().unwrap().as_rule() {
+                Rule::decimal => Schema::double,
+                _ => Schema::int
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Checks a lambda's body as if it were a function of its own.
+    fn lambda(&mut self, lambda: Token) -> Schema {
+        let returns = std::mem::replace(&mut self.returns, Schema::Any);
+        let public = std::mem::replace(&mut self.public, false);
+        self.env.push(HashMap::new());
+        for part in lambda.into_inner() {
+            match part.as_rule() {
+                Rule::params => for (name, schema) in parameters(part) {
+                    self.define(&name, schema);
+                },
+                Rule::scope => for statement in part.into_inner() {
+                    self.statement(statement);
+                },
+                _ => {
+                    self.expression(part);
+                }
+            };
+        }
+        self.env.pop();
+        self.returns = returns;
+        self.public = public;
+        Schema::Function
+    }
+
+    fn call(&mut self, name: &Token, args: Vec<(Token, Schema)>) -> Schema {
+        // Calls to a variable holding a function aren't checked until they run.
+        if let Some(schema) = self.local(name.as_str()) {
+            if !self.compatible(&schema, &Schema::Function) {
+                let message = format!("Cannot call {}", self.describe(&schema));
+                self.error(name, message);
+            }
+            return Schema::Any
+        }
+        let signature = match self.functions.get(name.as_str()) {
+            Some(signature) if signature.params.len() == args.len() => signature.clone(),
+            // Unknown functions and wrong arities are reported by the resolver.
+            _ => return Schema::Any
+        };
+        for ((at, given), (param, expected)) in args.iter().zip(signature.params.iter()) {
+            self.expect(at, given, expected, &format!("parameter {} of {}", param, name.as_str()));
+        }
+        signature.returns
+    }
+
+    fn method(&mut self, target: Schema, m: Token) -> Schema {
+        match m.as_rule() {
+            Rule::fieldAccess => {
+                let field = m.clone().into_inner().next().unwrap();
+                self.field(&m, target, field.as_str())
+            },
+            Rule::parameterIndex => {
+                let index = m.clone().into_inner().next().unwrap();
+                let key = self.expression(index.clone());
+                match self.expand(target.clone()) {
+                    Schema::Array(inner) => {
+                        self.expect(&index, &key, &Schema::Union(vec![Schema::int, Schema::double]), "an array index");
+                        inner[0].clone()
+                    },
+                    _ => match string_literal(&index) {
+                        Some(field) => self.field(&m, target, &field),
+                        None => self.field(&m, target, "")
+                    }
+                }
+            },
+            Rule::methodInvoke => {
+                let mut parts = m.clone().into_inner();
+                let name = parts.next().unwrap();
+                let args: Vec<(Token, Schema)> = parts.next().unwrap().into_inner().map(|a| (a.clone(), self.expression(a))).collect();
+                let expanded = self.expand(target.clone());
+                let unknown = self.kind(&target) == Kind::Unknown;
+                match name.as_str() {
+                    "len" => {
+                        if !unknown && !matches!(expanded, Schema::Array(_)) {
+                            let message = format!("Cannot take len() of {}", self.describe(&target));
+                            self.error(&m, message);
+                        }
+                        Schema::int
+                    },
+                    "keys" | "has" => {
+                        if !unknown && !matches!(expanded, Schema::Object(_) | Schema::Map(_)) {
+                            let message = format!("Cannot call {}() on {}", name.as_str(), self.describe(&target));
+                            self.error(&m, message);
+                        }
+                        if name.as_str() == "keys" { Schema::Array(vec![Schema::string]) } else { Schema::bool }
+                    },
+                    "push" => Schema::Any,
+                    _ => match ArrayMethod::named(name.as_str()) {
+                        Some(method) => self.array_method(&m, name.as_str(), method, target, args),
+                        None => {
+                            let mut all = vec![(m.clone(), target)];
+                            all.extend(args);
+                            self.call(&name, all)
+                        }
+                    }
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Checks a built in array method, returning the schema of its result.
+    fn array_method(&mut self, at: &Token, name: &str, method: ArrayMethod, target: Schema, args: Vec<(Token, Schema)>) -> Schema {
+        let element = match self.expand(target.clone()) {
+            Schema::Array(inner) => inner[0].clone(),
+            _ if self.kind(&target) == Kind::Unknown => Schema::Any,
+            _ => {
+                let message = format!("Cannot call {}() on {}", name, self.describe(&target));
+                self.error(at, message);
+                return Schema::Any
+            }
+        };
+        // Arities are checked by lowering.
+        let expected: Vec<Schema> = match method {
+            ArrayMethod::Map | ArrayMethod::Filter | ArrayMethod::Find | ArrayMethod::Any | ArrayMethod::All |
+            ArrayMethod::Sort => vec![Schema::Function],
+            ArrayMethod::Reduce => vec![Schema::Function, Schema::Any],
+            ArrayMethod::Slice => vec![Schema::int, Schema::int],
+            ArrayMethod::Concat => vec![Schema::Array(vec![Schema::Any])],
+            ArrayMethod::Reverse | ArrayMethod::IndexOf | ArrayMethod::Flatten => vec![]
+        };
+        for ((arg, given), expected) in args.iter().zip(expected.iter()) {
+            self.expect(arg, given, expected, &format!("the argument of {}()", name));
+        }
+        match method {
+            ArrayMethod::Filter | ArrayMethod::Sort | ArrayMethod::Slice | ArrayMethod::Reverse => target,
+            ArrayMethod::Find => Schema::Union(vec![element, Schema::none]),
+            ArrayMethod::Any | ArrayMethod::All => Schema::bool,
+            ArrayMethod::IndexOf => Schema::int,
+            ArrayMethod::Concat => match args.first() {
+                Some((_, other)) if self.describe(other) == self.describe(&target) => target,
+                _ => Schema::Array(vec![Schema::Any])
+            },
+            ArrayMethod::Map | ArrayMethod::Flatten => Schema::Array(vec![Schema::Any]),
+            ArrayMethod::Reduce => Schema::Any
+        }
+    }
+
+    /// Reads `field` from a value of schema `target`; an empty name stands for a key only known at runtime.
+    fn field(&mut self, at: &Token, target: Schema, field: &str) -> Schema {
+        match self.expand(target.clone()) {
+            Schema::Object(fields) => match fields.0.get(field) {
+                Some(schema) => schema.clone(),
+                None if field.is_empty() => Schema::Any,
+                None => {
+                    let message = format!("{} has no field {}", self.describe(&target), field);
+                    self.error(at, message);
+                    Schema::Any
+                }
+            },
+            Schema::Map(inner) => inner[0].clone(),
+            Schema::none | Schema::int | Schema::double | Schema::bool | Schema::string => {
+                let message = format!("Cannot read a field of {}", self.describe(&target));
+                self.error(at, message);
+                Schema::Any
+            },
+            _ => Schema::Any
+        }
+    }
+}
+
+/// The contents of an expression that is just a string literal.
+fn string_literal(expression: &Token) -> Option<String> {
+    let text = expression.as_str().trim();
+    let literal = expression.clone().into_inner().flatten().find(|p| p.as_rule() == Rule::string)?;
+    if literal.as_str() != text {
+        return None;
+    }
+    Some(text[1..text.len() - 1].to_string())
+}
@@ -1,30 +1,49 @@
 use rand;
+use std::collections::HashMap;
 use rand_core::RngCore;
 use crypto::ed25519;
 use tuna_compiler;
+use tuna_compiler::ir::Visibility;
+use tuna_compiler::artifact::{self, Format};
+use tuna_compiler::disasm;
+use tuna_interpreter::ops::Op;
 use tuna_interpreter::{self, State};
 use tuna_interpreter::data::*;
+use tuna_interpreter::schemas::Schema;
+use tuna_interpreter::roles;
 type Data =InterpreterType;
 
 async fn exec_test(code: &str, func: &str, args: Vec<Data>) {
     data_test(code, func, args, Data::None).await;
 }
 
-async fn data_test(code: &str, func: &str, mut args: Vec<Data>, expect: Data) {
+async fn data_test(code: &str, func: &str, args: Vec<Data>, expect: Data) {
+    let res = try_run(code, func, args).unwrap();
+    assert_eq!(expect, res);
+}
+
+fn try_run(code: &str, func: &str, args: Vec<Data>) -> Result<Data, String> {
+    with_globals(code, |run| run(func, args))
+}
+
+/// Runs `body` with a runner whose calls all share one compiled program and signing key.
+fn with_globals<T>(code: &str, body: impl FnOnce(&dyn Fn(&str, Vec<Data>) -> Result<Data, String>) -> T) -> T {
+    with_compiled(&tuna_compiler::compile(code).unwrap(), body)
+}
+
+fn with_compiled<T>(ex: &tuna_compiler::Compiled, body: impl FnOnce(&dyn Fn(&str, Vec<Data>) -> Result<Data, String>) -> T) -> T {
     let mut key = [0u8; 32];
     rand::thread_rng().fill_bytes(&mut key);
     let (priv_key, pub_key) = ed25519::keypair(&key);
-    let ex = tuna_compiler::compile(code).unwrap();
     let g = tuna_interpreter::Globals::new(
         &ex.schemas,
-
         &ex.fns,
+        &ex.lambdas,
         &priv_key,
         &pub_key
-    );
+    ).unwrap();
     
-    let res = g.run(&func.to_string(), &mut State::new(&mut args)).unwrap();
-    assert_eq!(expect, res);
+    body(&|func, mut args| g.run(&func.to_string(), &mut State::new(&mut args)))
 }
 
 #[tokio::test]
@@ -160,4 +179,1898 @@ async fn variables_across_functions_can_have_the_same_name() {
     func entry(a) {
         return vary(a)
     }"#, "entry", vec![Data::int(-1)], Data::int(-1)).await;
-}
\ No newline at end of file
+}
+#[test]
+fn reports_syntax_errors_with_a_location() {
+    let errs = tuna_compiler::compile_file("main.tuna", r#"
+    func broken() {
+        let = 5
+    }"#).err().unwrap();
+    assert_eq!(1, errs.len());
+    assert_eq!("E0001", errs[0].code.code());
+    assert_eq!("main.tuna", errs[0].span.file);
+    assert_eq!(3, errs[0].span.line);
+}
+
+#[test]
+fn collects_errors_across_functions() {
+    let errs = tuna_compiler::compile(r#"
+    func a() {
+        1 = 2
+    }
+
+    func b() {
+        'a' = 'b'
+    }"#).err().unwrap();
+    assert_eq!(2, errs.len());
+    assert_eq!((3, 9), (errs[0].span.line, errs[0].span.column));
+    assert_eq!((7, 9), (errs[1].span.line, errs[1].span.column));
+}
+
+#[tokio::test]
+async fn respects_operator_precedence() {
+    data_test(r#"
+    func math() {
+        return 1 + 2 * 3 - 4 / 2
+    }"#, "math", vec![], Data::int(5)).await;
+}
+
+#[tokio::test]
+async fn parentheses_override_precedence() {
+    data_test(r#"
+    func math() {
+        return (1 + 2) * (3 - 1)
+    }"#, "math", vec![], Data::int(6)).await;
+}
+
+#[tokio::test]
+async fn operators_are_left_associative() {
+    data_test(r#"
+    func math(a, b, c) {
+        return a - b - c
+    }"#, "math", vec![Data::int(10), Data::int(4), Data::int(3)], Data::int(3)).await;
+}
+
+#[tokio::test]
+async fn can_compare_values() {
+    data_test(r#"
+    func cmp(a, b) {
+        return [a < b, a <= b, a > b, a >= b, a == b, a != b]
+    }"#, "cmp", vec![Data::int(1), Data::int(2)], Data::Array(vec![
+        Data::bool(true), Data::bool(true), Data::bool(false), Data::bool(false), Data::bool(false), Data::bool(true)
+    ])).await;
+}
+
+#[tokio::test]
+async fn comparisons_bind_tighter_than_boolean_operators() {
+    data_test(r#"
+    func logic(a) {
+        return a > 1 and a < 3 or a == 10
+    }"#, "logic", vec![Data::int(2)], Data::bool(true)).await;
+    data_test(r#"
+    func logic(a) {
+        return false and true or a == true
+    }"#, "logic", vec![Data::bool(true)], Data::bool(true)).await;
+}
+
+#[tokio::test]
+async fn names_may_start_with_keywords() {
+    data_test(r#"
+    func f(order, nonce, android) {
+        return order or nonce and android
+    }"#, "f", vec![Data::bool(false), Data::bool(true), Data::bool(true)], Data::bool(true)).await;
+}
+
+#[tokio::test]
+async fn can_branch() {
+    let code = r#"
+    func sign(a) {
+        if a < 0 {
+            return 'negative'
+        } else if a == 0 {
+            return 'zero'
+        } else {
+            return 'positive'
+        }
+    }"#;
+    data_test(code, "sign", vec![Data::int(-3)], Data::string("negative".to_string())).await;
+    data_test(code, "sign", vec![Data::int(0)], Data::string("zero".to_string())).await;
+    data_test(code, "sign", vec![Data::int(3)], Data::string("positive".to_string())).await;
+}
+
+#[tokio::test]
+async fn falls_through_untaken_branches() {
+    let code = r#"
+    func f(a) {
+        if a {
+            return 'taken'
+        }
+        return 'skipped'
+    }"#;
+    data_test(code, "f", vec![Data::bool(true)], Data::string("taken".to_string())).await;
+    data_test(code, "f", vec![Data::bool(false)], Data::string("skipped".to_string())).await;
+}
+
+#[tokio::test]
+async fn can_nest_branches() {
+    let code = r#"
+    func quadrant(x, y) {
+        if x >= 0 {
+            if y >= 0 {
+                return 1
+            } else {
+                return 4
+            }
+        } else if y >= 0 {
+            return 2
+        }
+        return 3
+    }"#;
+    data_test(code, "quadrant", vec![Data::int(1), Data::int(1)], Data::int(1)).await;
+    data_test(code, "quadrant", vec![Data::int(-1), Data::int(1)], Data::int(2)).await;
+    data_test(code, "quadrant", vec![Data::int(-1), Data::int(-1)], Data::int(3)).await;
+    data_test(code, "quadrant", vec![Data::int(1), Data::int(-1)], Data::int(4)).await;
+}
+
+#[tokio::test]
+async fn branch_variables_are_scoped_to_the_branch() {
+    let code = r#"
+    func f(a) {
+        if a {
+            let x = 'inner'
+            let z = x
+        } else {
+            let w = 'other'
+        }
+        let y = 'outer'
+        return [a, y]
+    }"#;
+    data_test(code, "f", vec![Data::bool(true)], Data::Array(vec![Data::bool(true), Data::string("outer".to_string())])).await;
+    data_test(code, "f", vec![Data::bool(false)], Data::Array(vec![Data::bool(false), Data::string("outer".to_string())])).await;
+    // Each branch drops its variables, so y reuses the slot x and w had.
+    let ex = tuna_compiler::compile(code).unwrap();
+    let drops: Vec<usize> = ex.fns["f"].iter().filter_map(|op| match op {
+        Op::truncateHeap(n) => Some(*n),
+        _ => None
+    }).collect();
+    assert_eq!(drops, vec![2, 1]);
+    assert_eq!(ex.functions["f"].slots[1], vec!["x".to_string(), "w".to_string(), "y".to_string()]);
+}
+
+#[tokio::test]
+async fn statements_may_start_with_keyword_prefixed_names() {
+    data_test(r#"
+    func f(returned, elsewhere) {
+        returned
+        elsewhere
+        let letter = returned
+        return letter
+    }"#, "f", vec![Data::int(1), Data::int(2)], Data::int(1)).await;
+}
+
+fn obj(fields: Vec<(&str, Data)>) -> Data {
+    Data::Object(Obj(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect()))
+}
+
+#[tokio::test]
+async fn loops_visit_elements_in_order() {
+    data_test(r#"
+    func firstBig(arr) {
+        for x in arr {
+            if x > 2 {
+                return x
+            }
+        }
+        return none
+    }"#, "firstBig", vec![Data::Array(vec![Data::int(1), Data::int(5), Data::int(3)])], Data::int(5)).await;
+}
+
+#[tokio::test]
+async fn loops_over_empty_arrays_do_nothing() {
+    data_test(r#"
+    func f() {
+        for x in [] {
+            return 'looped'
+        }
+        return 'done'
+    }"#, "f", vec![], Data::string("done".to_string())).await;
+}
+
+#[tokio::test]
+async fn can_nest_loops() {
+    data_test(r#"
+    func pairSum(xs, ys, target) {
+        for x in xs {
+            for y in ys {
+                if x + y == target {
+                    return [x, y]
+                }
+            }
+        }
+        return none
+    }"#, "pairSum", vec![
+        Data::Array(vec![Data::int(1), Data::int(2), Data::int(3)]),
+        Data::Array(vec![Data::int(10), Data::int(20)]),
+        Data::int(22)
+    ], Data::Array(vec![Data::int(2), Data::int(20)])).await;
+}
+
+#[tokio::test]
+async fn loop_variables_are_scoped_to_the_loop() {
+    let code = r#"
+    func f(arr) {
+        for x in arr {
+            let doubled = x * 2
+            if doubled > 10 {
+                return doubled
+            }
+        }
+        let after = 'after'
+        return after
+    }"#;
+    data_test(code, "f", vec![Data::Array(vec![Data::int(1), Data::int(2)])], Data::string("after".to_string())).await;
+    data_test(code, "f", vec![Data::Array(vec![Data::int(1), Data::int(6)])], Data::int(12)).await;
+}
+
+#[tokio::test]
+async fn can_loop_over_object_keys() {
+    data_test(r#"
+    func f(o) {
+        for k in o {
+            return k
+        }
+    }"#, "f", vec![obj(vec![("b", Data::int(1)), ("a", Data::int(2))])], Data::string("a".to_string())).await;
+}
+
+#[tokio::test]
+async fn can_loop_over_keys_and_values() {
+    data_test(r#"
+    func keyOf(o, target) {
+        for key, value in o {
+            if value == target {
+                return key
+            }
+        }
+    }"#, "keyOf", vec![
+        obj(vec![("a", Data::int(1)), ("b", Data::int(2)), ("c", Data::int(3))]),
+        Data::int(2)
+    ], Data::string("b".to_string())).await;
+    data_test(r#"
+    func indexOf(arr, target) {
+        for i, value in arr {
+            if value == target {
+                return i
+            }
+        }
+    }"#, "indexOf", vec![
+        Data::Array(vec![Data::string("x".to_string()), Data::string("y".to_string())]),
+        Data::string("y".to_string())
+    ], Data::int(1)).await;
+}
+
+#[tokio::test]
+async fn can_reassign_variables() {
+    data_test(r#"
+    func sum(arr) {
+        let total = 0
+        for x in arr {
+            total = total + x
+        }
+        return total
+    }"#, "sum", vec![Data::Array(vec![Data::int(1), Data::int(2), Data::int(3)])], Data::int(6)).await;
+}
+
+#[tokio::test]
+async fn can_assign_nested_fields() {
+    data_test(r#"
+    func f(key) {
+        let o = {a: {b: 1}, list: [1, 2]}
+        o.a.b = 2
+        o.a[key] = 3
+        o.list[1] = 'two'
+        return o
+    }"#, "f", vec![Data::string("c".to_string())], obj(vec![
+        ("a", obj(vec![("b", Data::int(2)), ("c", Data::int(3))])),
+        ("list", Data::Array(vec![Data::int(1), Data::string("two".to_string())]))
+    ])).await;
+}
+
+#[tokio::test]
+async fn can_push_onto_arrays() {
+    data_test(r#"
+    func f() {
+        let arr = []
+        arr.push(1, 2)
+        let o = {inner: {items: []}}
+        o.inner.items.push('a', 'b')
+        return [arr, o]
+    }"#, "f", vec![], Data::Array(vec![
+        Data::Array(vec![Data::int(1), Data::int(2)]),
+        obj(vec![("inner", obj(vec![("items", Data::Array(vec![Data::string("a".to_string()), Data::string("b".to_string())]))]))])
+    ])).await;
+}
+
+#[test]
+fn pushes_evaluate_their_path_once() {
+    let code = r#"
+    func pick() {
+        return 0
+    }
+    func f(arr) {
+        arr[pick()].push(1, 2, 3)
+        return arr
+    }"#;
+    let ex = tuna_compiler::compile(code).unwrap();
+    let picks = ex.fns["f"].iter().filter(|op| match op {
+        Op::invoke{name, ..} => name == "pick",
+        _ => false
+    }).count();
+    assert_eq!(picks, 1);
+    let arr = Data::Array(vec![Data::Array(vec![])]);
+    assert_eq!(
+        try_run(code, "f", vec![arr]),
+        Ok(Data::Array(vec![Data::Array(vec![Data::int(1), Data::int(2), Data::int(3)])]))
+    );
+}
+
+#[test]
+fn arrays_reject_fractional_indices() {
+    let code = r#"
+    func f(arr, i) {
+        return arr[i]
+    }"#;
+    let arr = || Data::Array(vec![Data::int(1), Data::int(2)]);
+    assert_eq!(try_run(code, "f", vec![arr(), Data::double(1.0)]), Ok(Data::int(2)));
+    assert!(try_run(code, "f", vec![arr(), Data::double(0.9)]).is_err());
+}
+
+#[tokio::test]
+async fn can_delete_fields_and_elements() {
+    data_test(r#"
+    func f() {
+        let o = {a: 1, b: {c: 2, d: 3}, arr: ['x', 'y', 'z']}
+        delete o.a
+        delete o.b['c']
+        delete o.arr[1]
+        return o
+    }"#, "f", vec![], obj(vec![
+        ("b", obj(vec![("d", Data::int(3))])),
+        ("arr", Data::Array(vec![Data::string("x".to_string()), Data::string("z".to_string())]))
+    ])).await;
+}
+
+#[test]
+fn rejects_invalid_assignment_targets() {
+    let errs = tuna_compiler::compile(r#"
+    func f(a) {
+        f(a) = 1
+        a + 1 = 2
+        delete a
+    }"#).err().unwrap();
+    assert_eq!(3, errs.len());
+    assert!(errs.iter().all(|e| e.code.code() == "E0004"));
+}
+
+#[test]
+fn annotated_parameters_reject_mismatched_input() {
+    let code = "func f(a: int) { return a }";
+    assert_eq!(try_run(code, "f", vec![Data::int(3)]), Ok(Data::int(3)));
+    assert_eq!(
+        try_run(code, "f", vec![Data::string("3".to_string())]),
+        Err("Input did not match expectations for a".to_string())
+    );
+}
+
+#[test]
+fn type_declarations_are_compiled_into_schemas() {
+    let ex = tuna_compiler::compile(r#"
+    type Point = {x: double, y: double}
+    type Path = Point[]
+    "#).unwrap();
+    assert!(ex.schemas.contains_key("Point"));
+    assert!(matches!(ex.schemas.get("Path"), Some(Schema::Array(inner)) if matches!(inner[0], Schema::TypeAlias(ref n) if n == "Point")));
+
+    let code = r#"
+    type Point = {x: double, y: double}
+    func f(p: Point) { return p }"#;
+    let point = obj(vec![("x", Data::double(1.0)), ("y", Data::double(2.0))]);
+    assert_eq!(try_run(code, "f", vec![point.clone()]), Ok(point));
+    assert!(try_run(code, "f", vec![obj(vec![("x", Data::double(1.0))])]).is_err());
+}
+
+#[test]
+fn optional_array_and_union_annotations() {
+    let code = r#"
+    type Tagged = {name: string, tag: string?}
+    func f(a: int?, b: string[], c: int or string, d: Tagged) {}"#;
+    let ok = |a, b, c, d| try_run(code, "f", vec![a, b, c, d]).is_ok();
+    let tagged = obj(vec![("name", Data::string("n".to_string()))]);
+    let strs = Data::Array(vec![Data::string("s".to_string())]);
+
+    assert!(ok(Data::None, strs.clone(), Data::int(1), tagged.clone()));
+    assert!(ok(Data::int(1), Data::Array(vec![]), Data::string("c".to_string()), tagged.clone()));
+    assert!(!ok(Data::bool(true), strs.clone(), Data::int(1), tagged.clone()));
+    assert!(!ok(Data::None, Data::Array(vec![Data::int(1)]), Data::int(1), tagged.clone()));
+    assert!(!ok(Data::None, strs.clone(), Data::bool(false), tagged));
+    assert!(!ok(Data::None, strs, Data::int(1), obj(vec![("tag", Data::string("t".to_string()))])));
+}
+
+#[test]
+fn unknown_types_are_compile_errors() {
+    let errs = tuna_compiler::compile("func f(a: Missing[]) {}").err().unwrap();
+    assert_eq!(errs.len(), 1);
+    assert_eq!("E0005", errs[0].code.code());
+    assert_eq!((errs[0].span.line, errs[0].span.column), (1, 11));
+}
+
+const ADMIN: &str = r#"
+    role Admin {id: string}
+    role Guest {id: string}
+    func grant(id) { return Admin {id: id} }
+    func visit(id) { return Guest {id: id} }
+    func check(a: Admin) { return 'ok' }"#;
+
+#[test]
+fn role_declarations_register_role_schemas() {
+    let ex = tuna_compiler::compile(ADMIN).unwrap();
+    assert!(matches!(ex.schemas.get("Admin"), Some(Schema::Role(name, _)) if name == "Admin"));
+}
+
+#[test]
+fn signed_role_instances_are_accepted() {
+    with_globals(ADMIN, |run| {
+        let admin = run("grant", vec![Data::string("x".to_string())]).unwrap();
+        assert_eq!(run("check", vec![admin]), Ok(Data::string("ok".to_string())));
+    });
+}
+
+#[test]
+fn tampered_role_instances_are_rejected() {
+    with_globals(ADMIN, |run| {
+        let mut admin = run("grant", vec![Data::string("x".to_string())]).unwrap();
+        admin.set(vec![Data::string("_state".to_string()), Data::string("id".to_string())], Data::string("y".to_string())).unwrap();
+        assert!(run("check", vec![admin]).is_err());
+
+        let mut guest = run("visit", vec![Data::string("x".to_string())]).unwrap();
+        guest.set(vec![Data::string("_name".to_string())], Data::string("Admin".to_string())).unwrap();
+        assert!(run("check", vec![guest]).is_err());
+
+        let guest = run("visit", vec![Data::string("x".to_string())]).unwrap();
+        assert!(run("check", vec![guest]).is_err());
+    });
+}
+
+#[test]
+fn forged_role_instances_are_rejected() {
+    let forged = obj(vec![
+        ("_name", Data::string("Admin".to_string())),
+        ("_state", obj(vec![("id", Data::string("x".to_string()))])),
+        ("_sig", Data::Array(vec![Data::int(0); 64]))
+    ]);
+    assert!(try_run(ADMIN, "check", vec![forged]).is_err());
+}
+
+#[test]
+fn role_instances_must_match_their_role() {
+    assert!(try_run(ADMIN, "grant", vec![Data::int(1)]).is_err());
+}
+
+#[test]
+fn unknown_roles_are_compile_errors() {
+    let errs = tuna_compiler::compile("func f() { return Missing {id: 1} }").err().unwrap();
+    assert_eq!(errs.len(), 1);
+    assert_eq!("E0005", errs[0].code.code());
+    assert_eq!(errs[0].message, "Unknown role Missing");
+}
+
+#[tokio::test]
+async fn capitalized_names_before_a_scope_are_variables() {
+    data_test(r#"
+    role Admin {id: string}
+    func check(a: Admin) { return 'ok' }
+    func f(Flag, Items) {
+        let out = []
+        if Flag {
+            out.push(check(Admin {id: 'a'}))
+        } else if not Flag {}
+        for x in Items {
+            out.push(x)
+        }
+        while Flag {
+            break
+        }
+        if check(Admin {id: 'b'}) == 'ok' {
+            out.push('signed')
+        }
+        return out
+    }"#, "f", vec![Data::bool(true), Data::Array(vec![Data::int(1)])], Data::Array(vec![
+        Data::string("ok".to_string()),
+        Data::int(1),
+        Data::string("signed".to_string())
+    ])).await;
+}
+
+fn encoded(value: &Data) -> Vec<u8> {
+    let mut out = vec![];
+    roles::encode(value, &mut out);
+    out
+}
+
+#[test]
+fn role_encoding_is_canonical() {
+    let state = obj(vec![("b", Data::int(1)), ("a", Data::Array(vec![Data::string("x".to_string()), Data::None]))]);
+    assert_eq!(encoded(&state), vec![
+        6, 0, 0, 0, 0, 0, 0, 0, 2,
+        0, 0, 0, 0, 0, 0, 0, 1, b'a',
+        5, 0, 0, 0, 0, 0, 0, 0, 2,
+        4, 0, 0, 0, 0, 0, 0, 0, 1, b'x',
+        0,
+        0, 0, 0, 0, 0, 0, 0, 1, b'b',
+        2, 0, 0, 0, 0, 0, 0, 0, 1
+    ]);
+
+    let split = |a: &str, b: &str| Data::Array(vec![Data::string(a.to_string()), Data::string(b.to_string())]);
+    assert_ne!(encoded(&split("a", "bc")), encoded(&split("ab", "c")));
+    assert_ne!(encoded(&Data::int(1)), encoded(&Data::double(1.0)));
+    let claims = roles::Claims {iat: 0, exp: 1, kid: "k".to_string()};
+    assert_ne!(roles::digest("Admin", None, &claims), roles::digest("Admin", Some(&obj(vec![])), &claims));
+}
+
+#[test]
+fn role_instances_carry_the_signing_version() {
+    with_globals(ADMIN, |run| {
+        let mut admin = run("grant", vec![Data::string("x".to_string())]).unwrap();
+        assert_eq!(admin.clone().to_obj().unwrap().get("_v"), Some(&Data::int(roles::ROLE_VERSION)));
+
+        admin.set(vec![Data::string("_v".to_string())], Data::int(roles::ROLE_VERSION + 1)).unwrap();
+        assert!(run("check", vec![admin]).is_err());
+    });
+}
+
+fn keypair() -> ([u8; 64], [u8; 32]) {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    ed25519::keypair(&key)
+}
+
+fn call(g: &tuna_interpreter::Globals, func: &str, mut args: Vec<Data>) -> Result<Data, String> {
+    g.run(&func.to_string(), &mut State::new(&mut args))
+}
+
+fn field(instance: &Data, name: &str) -> Data {
+    instance.clone().to_obj().unwrap().remove(name).unwrap()
+}
+
+#[test]
+fn role_instances_carry_issuance_claims() {
+    with_globals(ADMIN, |run| {
+        let admin = run("grant", vec![Data::string("x".to_string())]).unwrap();
+        match (field(&admin, "_iat"), field(&admin, "_exp"), field(&admin, "_kid")) {
+            (Data::int(iat), Data::int(exp), Data::string(_)) => assert_eq!(exp - iat, roles::DEFAULT_TTL),
+            other => panic!("Unexpected claims {:?}", other)
+        };
+
+        let mut extended = admin.clone();
+        extended.set(vec![Data::string("_exp".to_string())], Data::int(i64::MAX)).unwrap();
+        assert!(run("check", vec![extended]).is_err());
+    });
+}
+
+#[test]
+fn expired_role_instances_are_rejected() {
+    let ex = tuna_compiler::compile(ADMIN).unwrap();
+    let (priv_key, pub_key) = keypair();
+    let mut g = tuna_interpreter::Globals::new(&ex.schemas, &ex.fns, &ex.lambdas, &priv_key, &pub_key).unwrap();
+    g.keys.ttl = 0;
+    let admin = call(&g, "grant", vec![Data::string("x".to_string())]).unwrap();
+    assert!(call(&g, "check", vec![admin]).is_err());
+}
+
+#[test]
+fn rotated_keys_keep_verifying_unexpired_instances() {
+    let ex = tuna_compiler::compile(ADMIN).unwrap();
+    let (priv_key, pub_key) = keypair();
+    let mut g = tuna_interpreter::Globals::new(&ex.schemas, &ex.fns, &ex.lambdas, &priv_key, &pub_key).unwrap();
+    let old = call(&g, "grant", vec![Data::string("x".to_string())]).unwrap();
+
+    let (priv_key, pub_key) = keypair();
+    g.rotate_key(priv_key, pub_key);
+    let new = call(&g, "grant", vec![Data::string("x".to_string())]).unwrap();
+    assert_ne!(field(&old, "_kid"), field(&new, "_kid"));
+    assert_eq!(field(&new, "_kid"), Data::string(roles::key_id(&pub_key)));
+    assert!(call(&g, "check", vec![old.clone()]).is_ok());
+    assert!(call(&g, "check", vec![new.clone()]).is_ok());
+
+    g.keys.retired.clear();
+    assert!(call(&g, "check", vec![old]).is_err());
+    assert!(call(&g, "check", vec![new]).is_ok());
+}
+
+#[test]
+fn legacy_role_instances_are_only_accepted_until_the_cutoff() {
+    let ex = tuna_compiler::compile(ADMIN).unwrap();
+    let (priv_key, pub_key) = keypair();
+    let mut g = tuna_interpreter::Globals::new(&ex.schemas, &ex.fns, &ex.lambdas, &priv_key, &pub_key).unwrap();
+    let state = obj(vec![("id", Data::string("x".to_string()))]);
+    let sig = ed25519::signature(&roles::legacy_digest("Admin", Some(&state)), &priv_key);
+    let legacy = obj(vec![
+        ("_name", Data::string("Admin".to_string())),
+        ("_state", state),
+        ("_sig", Data::Array(sig.iter().map(|b| Data::int(*b as i64)).collect())),
+        ("_v", Data::int(roles::LEGACY_ROLE_VERSION))
+    ]);
+    assert!(call(&g, "check", vec![legacy.clone()]).is_err());
+
+    g.accept_legacy_roles_until(roles::now() + 60);
+    assert!(call(&g, "check", vec![legacy.clone()]).is_ok());
+
+    let (priv_key, pub_key) = keypair();
+    g.rotate_key(priv_key, pub_key);
+    assert!(call(&g, "check", vec![legacy.clone()]).is_ok());
+
+    g.accept_legacy_roles_until(roles::now());
+    assert!(call(&g, "check", vec![legacy]).is_err());
+}
+
+#[tokio::test]
+async fn integer_literals_stay_integers() {
+    data_test(r#"
+    func f() {
+        return [1 + 1, 7 / 2, 1.5 * 2, 2 * 0.5]
+    }"#, "f", vec![], Data::Array(vec![Data::int(2), Data::int(3), Data::double(3.0), Data::double(1.0)])).await;
+}
+
+#[tokio::test]
+async fn ints_and_doubles_are_equal_by_value() {
+    data_test(r#"
+    func f(x, arr) {
+        return [x == 1, x != 1, 1 == x, x == 2, arr.index_of(1), arr.index_of(2.0)]
+    }"#, "f", vec![Data::double(1.0), Data::Array(vec![Data::double(0.5), Data::double(1.0), Data::int(2)])], Data::Array(vec![
+        Data::bool(true), Data::bool(false), Data::bool(true), Data::bool(false), Data::int(1), Data::int(2)
+    ])).await;
+}
+
+#[tokio::test]
+async fn number_literal_forms() {
+    data_test(r#"
+    func f() {
+        let min = -9223372036854775808
+        let neg = -0x10
+        return [0xff, 0b1010, 1_000_000, 0xFF_FF, 1e3, 2.5E-1, 1_0.0_1, min, neg]
+    }"#, "f", vec![], Data::Array(vec![
+        Data::int(255), Data::int(10), Data::int(1000000), Data::int(65535),
+        Data::double(1000.0), Data::double(0.25), Data::double(10.01), Data::int(i64::MIN), Data::int(-16)
+    ])).await;
+}
+
+#[tokio::test]
+async fn methods_bind_before_a_leading_minus() {
+    data_test(r#"
+    func inc(x) {
+        return x + 1
+    }
+    func f() {
+        let a = -2.inc()
+        let b = -2
+        return [a, b]
+    }"#, "f", vec![], Data::Array(vec![Data::int(-3), Data::int(-2)])).await;
+}
+
+#[test]
+fn overflowing_literals_are_compile_errors() {
+    let errs = tuna_compiler::compile(r#"
+    func f() {
+        let a = 9223372036854775808
+        let b = 0x1_0000_0000_0000_0000
+        let c = 1e400
+    }"#).err().unwrap();
+    assert_eq!(3, errs.len());
+    assert!(errs.iter().all(|e| e.code.code() == "E0003"));
+    assert_eq!("Integer 9223372036854775808 does not fit in 64 bits", errs[0].message);
+    assert_eq!((3, 17), (errs[0].span.line, errs[0].span.column));
+}
+
+#[test]
+fn integer_arithmetic_is_checked() {
+    assert_eq!(try_run("func f(a) { return a + 1 }", "f", vec![Data::int(i64::MAX)]), Err("Integer overflow".to_string()));
+    assert_eq!(try_run("func f(a) { return 1 / a }", "f", vec![Data::int(0)]), Err("Division by zero".to_string()));
+    assert_eq!(try_run("func f(a) { return a / -1 }", "f", vec![Data::int(i64::MIN)]), Err("Integer overflow".to_string()));
+}
+
+#[tokio::test]
+async fn can_read_fields_and_indices() {
+    data_test(r#"
+    func f(o, key) {
+        return [o.a.b, o.list[1], o['a'].b, o.a[key], o.list[0].x, {n: [5, 6]}.n[1]]
+    }"#, "f", vec![
+        obj(vec![
+            ("a", obj(vec![("b", Data::int(1)), ("c", Data::int(2))])),
+            ("list", Data::Array(vec![obj(vec![("x", Data::int(3))]), Data::int(4)]))
+        ]),
+        Data::string("c".to_string())
+    ], Data::Array(vec![Data::int(1), Data::int(4), Data::int(1), Data::int(2), Data::int(3), Data::int(6)])).await;
+}
+
+#[tokio::test]
+async fn built_in_methods() {
+    data_test(r#"
+    func f(o) {
+        let arr = o.items
+        arr.push(3)
+        return [o.keys(), arr.len(), o.items.len(), o.has('items'), o.has('missing'), o.keys().len()]
+    }"#, "f", vec![obj(vec![("items", Data::Array(vec![Data::int(1), Data::int(2)])), ("b", Data::None)])], Data::Array(vec![
+        Data::Array(vec![Data::string("b".to_string()), Data::string("items".to_string())]),
+        Data::int(3),
+        Data::int(2),
+        Data::bool(true),
+        Data::bool(false),
+        Data::int(2)
+    ])).await;
+}
+
+#[tokio::test]
+async fn methods_fall_back_to_functions() {
+    data_test(r#"
+    func double(x) { return x * 2 }
+    func add(x, y) { return x + y }
+    func f(a) {
+        return a.double().add(1)
+    }"#, "f", vec![Data::int(4)], Data::int(9)).await;
+}
+
+#[test]
+fn rejects_unknown_methods_and_bad_calls() {
+    let errs = tuna_compiler::compile(r#"
+    func f(a) {
+        let x = a.missing()
+        let y = nothing(a)
+        let z = a.len(1)
+        let w = a.push(1)
+    }"#).err().unwrap();
+    let found: Vec<(&str, &str)> = errs.iter().map(|e| (e.code.code(), e.message.as_str())).collect();
+    assert_eq!(found, vec![
+        ("E0006", "Unknown method missing"),
+        ("E0006", "Unknown function nothing"),
+        ("E0007", "len() takes 0 argument(s) but 1 were given"),
+        ("E0004", "push() can only be used as a statement on a variable")
+    ]);
+}
+
+#[tokio::test]
+async fn not_negates_booleans() {
+    data_test(r#"
+    func f(a, b) {
+        return [not a, not not a, not a == b, not a and b, not (a or b), not b or a]
+    }"#, "f", vec![Data::bool(true), Data::bool(false)], Data::Array(vec![
+        Data::bool(false),
+        Data::bool(true),
+        Data::bool(true),
+        Data::bool(false),
+        Data::bool(false),
+        Data::bool(true)
+    ])).await;
+}
+
+#[tokio::test]
+async fn unary_minus_negates_numbers() {
+    data_test(r#"
+    func f(x, o) {
+        let a = -x
+        let b = -x * 2
+        let c = 3 - -x
+        let d = -o.items.len()
+        let e = -(x + 1)
+        let g = - -x
+        let h = -o.ratio
+        return [a, b, c, d, e, g, h]
+    }"#, "f", vec![Data::int(4), obj(vec![("items", Data::Array(vec![Data::None])), ("ratio", Data::double(0.5))])], Data::Array(vec![
+        Data::int(-4),
+        Data::int(-8),
+        Data::int(7),
+        Data::int(-1),
+        Data::int(-5),
+        Data::int(4),
+        Data::double(-0.5)
+    ])).await;
+}
+
+#[test]
+fn unary_operators_check_their_operands() {
+    assert_eq!(try_run("func f(a) { return -a }", "f", vec![Data::int(i64::MIN)]), Err("Integer overflow".to_string()));
+    assert!(try_run("func f(a) { return -a }", "f", vec![Data::string("a".to_string())]).is_err());
+    assert!(try_run("func f(a) { return not a }", "f", vec![Data::int(1)]).is_err());
+}
+
+fn diagnostics(code: &str) -> Vec<(&'static str, String, usize, usize)> {
+    tuna_compiler::compile(code).err().unwrap().into_iter()
+        .map(|e| (e.code.code(), e.message, e.span.line, e.span.column))
+        .collect()
+}
+
+#[test]
+fn reports_undefined_names() {
+    assert_eq!(diagnostics(r#"
+    func f(a) {
+        if a {
+            let inner = 1
+        }
+        return [inner, missing, T, a]
+    }
+    type T = int"#), vec![
+        ("E0009", "Undefined variable inner".to_string(), 6, 17),
+        ("E0009", "Undefined variable missing".to_string(), 6, 24),
+        ("E0009", "T is not a variable".to_string(), 6, 33)
+    ]);
+}
+
+#[test]
+fn reports_duplicate_definitions() {
+    assert_eq!(diagnostics(r#"
+    type T = int
+    func f(a, a) {
+        let b = 1
+        let b = 2
+    }
+    func T() {}"#), vec![
+        ("E0008", "T collides with something in scope.".to_string(), 7, 10),
+        ("E0008", "a collides with something in scope.".to_string(), 3, 15),
+        ("E0008", "b collides with something in scope.".to_string(), 5, 13)
+    ]);
+}
+
+#[tokio::test]
+async fn inner_scopes_may_shadow_outer_names() {
+    data_test(r#"
+    func f(x) {
+        for x in [1, 2] {
+            let y = x
+        }
+        if true {
+            let x = 'inner'
+        }
+        return x
+    }"#, "f", vec![Data::string("outer".to_string())], Data::string("outer".to_string())).await;
+}
+
+#[test]
+fn checks_calls_against_declared_functions() {
+    assert_eq!(diagnostics(r#"
+    func add(a, b) { return a + b }
+    func f(x) {
+        let one = add(x)
+        let two = x.add()
+        let three = x.add(1)
+        return missing(x)
+    }"#), vec![
+        ("E0007", "add() takes 2 argument(s) but 1 were given".to_string(), 4, 19),
+        ("E0007", "add() takes 2 argument(s) but 1 were given".to_string(), 5, 21),
+        ("E0006", "Unknown function missing".to_string(), 7, 16)
+    ]);
+}
+
+#[test]
+fn rejects_writes_to_constants() {
+    assert_eq!(diagnostics(r#"
+    func f() {
+        const c = {list: []}
+        let v = 1
+        v = 2
+        c = 3
+        c.list[0] = 1
+        c.list.push(1)
+        delete c.list
+    }"#).into_iter().map(|(code, msg, line, _)| (code, msg, line)).collect::<Vec<_>>(), vec![
+        ("E0010", "Cannot assign to constant c".to_string(), 6),
+        ("E0010", "Cannot assign to constant c".to_string(), 7),
+        ("E0010", "Cannot assign to constant c".to_string(), 8),
+        ("E0010", "Cannot assign to constant c".to_string(), 9)
+    ]);
+}
+
+#[test]
+fn rejects_writes_to_functions() {
+    assert_eq!(diagnostics(r#"
+    func g() {}
+    func f() {
+        g = 1
+        g.push(1)
+        delete g.x
+    }"#).into_iter().map(|(code, msg, line, _)| (code, msg, line)).collect::<Vec<_>>(), vec![
+        ("E0004", "Cannot assign to function g".to_string(), 4),
+        ("E0004", "Cannot assign to function g".to_string(), 5),
+        ("E0004", "Cannot assign to function g".to_string(), 6)
+    ]);
+}
+
+#[tokio::test]
+async fn constants_can_be_read() {
+    data_test(r#"
+    func f() {
+        const c = 2
+        return c * c
+    }"#, "f", vec![], Data::int(4)).await;
+}
+
+fn type_errors(code: &str) -> Vec<String> {
+    match tuna_compiler::compile(code) {
+        Ok(_) => vec![],
+        Err(errs) => errs.into_iter().map(|e| {
+            assert_eq!("E0011", e.code.code());
+            e.message
+        }).collect()
+    }
+}
+
+#[test]
+fn reports_operator_type_mismatches() {
+    assert_eq!(type_errors(r#"
+    func f(a: int, s: string) {
+        let x = 'a' - 1
+        let y = s * a
+        let z = a < 'b'
+        let w = not a
+        let v = -s
+        let ok = s + a + 1.5
+        return x
+    }"#), vec![
+        "Cannot apply - to string and int",
+        "Cannot apply * to string and int",
+        "Cannot apply < to int and string",
+        "Expected bool for not but found int",
+        "Cannot negate string"
+    ]);
+}
+
+#[test]
+fn reports_missing_fields_from_schemas() {
+    assert_eq!(type_errors(r#"
+    type User = {name: string, tags: string[], nick: string?}
+    func f(u: User) {
+        let a = u.name
+        let b = u.email
+        let c = u['age']
+        let d = u.tags[0].len()
+        let e = u.name.first
+        let g = u.tags['x']
+        let h = u.nick
+    }"#), vec![
+        "User has no field email",
+        "User has no field age",
+        "Cannot take len() of string",
+        "Cannot read a field of string",
+        "Expected int or double for an array index but found string"
+    ]);
+}
+
+#[test]
+fn checks_arguments_against_parameter_schemas() {
+    assert_eq!(type_errors(r#"
+    role Admin {id: string}
+    func secret(a: Admin, n: int?, p: {x: double}) {}
+    func f() {
+        secret(Admin {id: 'x'} none {x: 1})
+        secret(1 2 {x: 1 y: 2})
+        secret(Admin {id: 2} 'n' {x: 'one'})
+    }"#), vec![
+        "Expected Admin for parameter a of secret but found int",
+        "Expected {x: double} for parameter p of secret but found {x: int, y: int}",
+        "Expected {id: string} for role Admin but found {id: int}",
+        "Expected int or none for parameter n of secret but found string",
+        "Expected {x: double} for parameter p of secret but found {x: string}"
+    ]);
+}
+
+#[test]
+fn checks_conditions_and_loops() {
+    assert_eq!(type_errors(r#"
+    func f(n: int, arr: int[]) {
+        if n {
+        }
+        for x in n {
+        }
+        for x in arr {
+            let s = x.len()
+        }
+        for i x in arr {
+            if i == x {
+            }
+        }
+    }"#), vec![
+        "Expected bool for a condition but found int",
+        "Cannot loop over int",
+        "Cannot take len() of int"
+    ]);
+}
+
+#[test]
+fn degrades_to_any_where_inference_cannot_decide() {
+    assert_eq!(type_errors(r#"
+    type Point = {x: double, y: double}
+    func f(a, p: Point, maybe: int?) {
+        let total = 0
+        for v in a {
+            total = total + v
+        }
+        total = 'reset'
+        let n = total.len()
+        let o = {a: 1}
+        o.b = 2
+        let b = o.b
+        let q = a.anything - p.x
+        let r = maybe + 1
+        return [n, b, q, r]
+    }"#), Vec::<String>::new());
+}
+
+#[test]
+fn return_values_must_match_the_declared_type() {
+    let code = r#"
+    type Point = {x: int, y: int}
+    func echo(a): Point {
+        return a
+    }
+    func maybe(a): int? {
+        if a {
+            return 1
+        }
+    }
+    func nothing(): int {
+    }"#;
+    with_globals(code, |run| {
+        let point = obj(vec![("x", Data::int(1)), ("y", Data::int(2))]);
+        assert_eq!(run("echo", vec![point.clone()]), Ok(point));
+        assert_eq!(run("echo", vec![Data::string("secret".to_string())]), Err("Return value did not match expectations".to_string()));
+        assert_eq!(run("maybe", vec![Data::bool(true)]), Ok(Data::int(1)));
+        assert_eq!(run("maybe", vec![Data::bool(false)]), Ok(Data::None));
+        assert_eq!(run("nothing", vec![]), Err("Return value did not match expectations".to_string()));
+    });
+}
+
+#[test]
+fn checks_returns_against_the_declared_type() {
+    assert_eq!(type_errors(r#"
+    func name(id: int): string {
+        if id == 0 {
+            return
+        }
+        return id
+    }
+    func f(): int {
+        let n = name(1)
+        return n - 1
+    }"#), vec![
+        "Expected string for the return value but found none",
+        "Expected string for the return value but found int",
+        "Cannot apply - to string and int"
+    ]);
+}
+
+#[test]
+fn describes_functions_in_compiled_output() {
+    let ex = tuna_compiler::compile(r#"
+    type Point = {x: int, y: int}
+    /// Moves a point.
+    ///
+    ///Returns the moved point.
+    pub func shift(p: Point, by): Point {
+        // Plain comments are dropped.
+        return {x: p.x + by, y: p.y + by}
+    }
+    func helper() {}
+    "#).unwrap();
+
+    let shift = ex.functions.get("shift").unwrap();
+    assert_eq!(shift.name, "shift");
+    assert_eq!(shift.visibility, Visibility::Public);
+    assert_eq!(shift.doc.as_deref(), Some("Moves a point.\n\nReturns the moved point."));
+    let params: Vec<&str> = shift.params.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(params, vec!["p", "by"]);
+    assert!(matches!(shift.params[0].1, Schema::TypeAlias(ref n) if n == "Point"));
+    assert!(matches!(shift.params[1].1, Schema::Any));
+    assert!(matches!(shift.returns, Schema::TypeAlias(ref n) if n == "Point"));
+
+    let helper = ex.functions.get("helper").unwrap();
+    assert_eq!(helper.visibility, Visibility::Private);
+    assert_eq!(helper.doc, None);
+    assert!(matches!(helper.returns, Schema::Any));
+}
+
+#[tokio::test]
+async fn comments_are_ignored_outside_strings() {
+    data_test(r#"
+    // A comment before any declaration.
+    func f() {
+        let url = 'https://example.com' // trailing comment
+        return url
+    }"#, "f", vec![], Data::string("https://example.com".to_string())).await;
+}
+
+#[test]
+fn doc_comments_elsewhere_are_ordinary_comments() {
+    let ex = tuna_compiler::compile(r#"
+    /// A point.
+    type Point = {x: int}
+    /// An administrator.
+    role Admin {id: string}
+    /// Shared state.
+    const store = {}
+    /// Documented.
+    pub func f() {
+        /// Not documentation.
+        let p = {x: 1}
+        return p.x /// Nor this.
+    }
+    /// Trailing.
+    "#).unwrap();
+    assert_eq!(ex.functions["f"].doc.as_deref(), Some("Documented."));
+    with_compiled(&ex, |run| assert_eq!(run("f", vec![]), Ok(Data::int(1))));
+}
+
+const ARTIFACT_PROGRAM: &str = r#"
+    type Point = {x: int, y: double}
+    role Admin {id: string}
+    /// Sums a point.
+    pub func total(p: Point): double {
+        return p.x + p.y
+    }
+    func promote(id: string) {
+        return Admin {id: id}
+    }
+    "#;
+
+fn check_loaded_artifact(loaded: &tuna_compiler::Compiled) {
+    let info = loaded.functions.get("total").unwrap();
+    assert_eq!(info.visibility, Visibility::Public);
+    assert_eq!(info.doc.as_deref(), Some("Sums a point."));
+    assert!(matches!(info.returns, Schema::double));
+    assert!(matches!(loaded.schemas.get("Admin"), Some(Schema::Role(name, _)) if name == "Admin"));
+    with_compiled(loaded, |run| {
+        let point = obj(vec![("x", Data::int(1)), ("y", Data::double(0.5))]);
+        assert_eq!(run("total", vec![point]), Ok(Data::double(1.5)));
+        assert_eq!(run("total", vec![Data::int(1)]), Err("Input did not match expectations for p".to_string()));
+        assert!(run("promote", vec![Data::string("a".to_string())]).is_ok());
+    });
+}
+
+#[test]
+fn compiled_programs_round_trip_through_json() {
+    let json = tuna_compiler::compile(ARTIFACT_PROGRAM).unwrap().to_json().unwrap();
+    check_loaded_artifact(&tuna_compiler::Compiled::from_json(&json).unwrap());
+}
+
+#[test]
+fn compiled_programs_round_trip_through_binary() {
+    let bytes = tuna_compiler::compile(ARTIFACT_PROGRAM).unwrap().to_binary().unwrap();
+    assert_eq!(&bytes[..4], b"TUNA");
+    check_loaded_artifact(&tuna_compiler::Compiled::from_binary(&bytes).unwrap());
+}
+
+#[test]
+fn artifacts_are_loaded_in_either_format() {
+    let ex = tuna_compiler::compile(ARTIFACT_PROGRAM).unwrap();
+    for (format, ext) in [(Format::Json, "json"), (Format::Binary, "tunac")] {
+        let path = std::env::temp_dir().join(format!("artifact-{}-{}.{}", std::process::id(), ext, ext));
+        ex.save(&path, format).unwrap();
+        let loaded = tuna_compiler::Compiled::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        check_loaded_artifact(&loaded.unwrap());
+    }
+}
+
+#[test]
+fn artifact_shape_is_pinned() {
+    // A change here means stale artifacts would be misread, so bump ARTIFACT_VERSION with it.
+    let ex = tuna_compiler::compile("pub func f(a: int) { return a }").unwrap();
+    assert_eq!(ex.to_json().unwrap(), concat!(
+        r#"{"version":5,"compiled":{"schemas":{},"stores":{},"fns":{"f":["#,
+        r#"{"kind":"assertHeapLen","data":1},"#,
+        r#"{"kind":"enforceSchemaInstanceOnHeap","data":{"schema":{"kind":"int"},"heap_pos":0}},"#,
+        r#"{"kind":"conditonallySkipXops","data":1},"#,
+        r#"{"kind":"raiseError","data":"Input did not match expectations for a"},"#,
+        r#"{"kind":"copyFromHeap","data":0},"#,
+        r#"{"kind":"returnStackTop"}]},"lambdas":{},"#,
+        r#""functions":{"f":{"name":"f","visibility":"Public","params":[["a",{"kind":"int"}]],"#,
+        r#""returns":{"kind":"Any"},"doc":null,"slots":[["a"]]}}}}"#
+    ));
+}
+
+#[test]
+fn rejects_artifacts_from_other_versions() {
+    let ex = tuna_compiler::compile(ARTIFACT_PROGRAM).unwrap();
+    let other = artifact::ARTIFACT_VERSION + 1;
+    let expected = format!("Artifact has version {} but this build reads version {}", other, artifact::ARTIFACT_VERSION);
+
+    let json = ex.to_json().unwrap().replacen(
+        &format!("\"version\":{}", artifact::ARTIFACT_VERSION),
+        &format!("\"version\":{}", other), 1);
+    assert_eq!(tuna_compiler::Compiled::from_json(&json).err(), Some(expected.clone()));
+
+    let mut bytes = ex.to_binary().unwrap();
+    bytes[4..8].copy_from_slice(&other.to_be_bytes());
+    assert_eq!(tuna_compiler::Compiled::from_binary(&bytes).err(), Some(expected));
+    assert_eq!(tuna_compiler::Compiled::from_binary(b"{}").err(), Some("Not a tuna artifact".to_string()));
+}
+
+fn tuna(args: &[&str]) -> (bool, String, String) {
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_tuna")).args(args).output().unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap(), String::from_utf8(out.stderr).unwrap())
+}
+
+#[test]
+fn cli_checks_builds_and_runs_programs() {
+    let dir = std::env::temp_dir().join(format!("tuna-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("prog.tuna");
+    let broken = dir.join("broken.tuna");
+    let key = dir.join("key.json");
+    std::fs::write(&source, "pub func add(a: int, b: int): int {\n    return a + b\n}\n").unwrap();
+    std::fs::write(&broken, "func f() {\n    return x\n}\n").unwrap();
+    let path = |p: &std::path::Path| p.to_str().unwrap().to_string();
+
+    assert_eq!(tuna(&["check", &path(&source)]), (true, "".to_string(), "".to_string()));
+    let (ok, _, err) = tuna(&["check", &path(&broken)]);
+    assert!(!ok);
+    assert_eq!(err, format!("{}:2:12: error[E0009]: Undefined variable x\n", path(&broken)));
+
+    assert_eq!(tuna(&["run", &path(&source), "add", "--args", "[1, 2]"]), (true, "3\n".to_string(), "".to_string()));
+    assert!(tuna(&["keygen", "-o", &path(&key)]).0);
+    assert!(tuna(&["build", &path(&source), "--format", "binary"]).0);
+    let artifact = path(&dir.join("prog.tunac"));
+    assert_eq!(tuna(&["run", &artifact, "add", "--args", "[2, 2]", "--key", &path(&key)]), (true, "4\n".to_string(), "".to_string()));
+    let (ok, _, err) = tuna(&["run", &artifact, "add", "--args", "['a', 2]"]);
+    assert!(!ok && err.starts_with("--args must be a JSON array"));
+    let (ok, listing, _) = tuna(&["disasm", &artifact, "add"]);
+    assert!(ok && listing.starts_with("; func add\n"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disassembles_with_labels_and_slot_names() {
+    let ex = tuna_compiler::compile(r#"
+    func count(limit: int) {
+        let total = 0
+        for x in [1, 2, 3] {
+            if x < limit {
+                total = total + x
+            }
+        }
+        return total
+    }"#).unwrap();
+    let ops = &ex.fns["count"];
+    let listing = disasm::disassemble(ops, &ex.functions["count"].slots);
+
+    assert!(listing.starts_with("     0  assertHeapLen 1\n     1  enforceSchemaInstanceOnHeap {\"heap_pos\":0,\"schema\":{\"kind\":\"int\"}}  ; limit\n"));
+    assert!(listing.contains("copyFromHeap 1  ; total\n"));
+    assert!(listing.contains("; x\n"));
+    for (i, op) in ops.iter().enumerate() {
+        if let Some(target) = disasm::jump_target(i, op) {
+            assert!(listing.contains(&format!("L{}:\n", target)));
+        }
+    }
+
+    let reassembled = disasm::assemble(&listing).unwrap();
+    assert_eq!(disasm::disassemble(&reassembled, &ex.functions["count"].slots), listing);
+}
+
+#[test]
+fn runs_hand_written_assembly() {
+    let ops = disasm::assemble(r#"
+        ; Counts down from the argument, returning how many steps it took.
+        assertHeapLen 1
+        instantiate 0
+        moveStackTopToHeap
+    top:
+        copyFromHeap 0
+        instantiate 0
+        lesseq
+        conditonallySkipXops done
+        copyFromHeap 0
+        instantiate 1
+        nMinus
+        overwriteArg 0
+        copyFromHeap 1
+        instantiate 1
+        plus
+        overwriteArg 1
+        offsetOpCursor top
+    done:
+        copyFromHeap 1
+        returnStackTop
+    "#).unwrap();
+    assert!(matches!(ops[6], Op::conditonallySkipXops(9)));
+    assert!(matches!(ops[15], Op::offsetOpCursor{offset: 12, fwd: false}));
+
+    let fns = HashMap::from([("steps".to_string(), ops)]);
+    let (schemas, lambdas) = (HashMap::new(), HashMap::new());
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    let (priv_key, pub_key) = ed25519::keypair(&key);
+    let g = tuna_interpreter::Globals::new(&schemas, &fns, &lambdas, &priv_key, &pub_key).unwrap();
+    assert_eq!(g.run(&"steps".to_string(), &mut State::new(&mut vec![Data::int(4)])), Ok(Data::int(4)));
+}
+
+#[test]
+fn reports_assembly_errors_by_line() {
+    assert!(disasm::assemble("noop\nfrobnicate").err().unwrap().starts_with("line 2: unknown variant `frobnicate`"));
+    assert_eq!(disasm::assemble("offsetOpCursor nowhere").err(), Some("line 1: Unknown label nowhere".to_string()));
+    assert_eq!(disasm::assemble("a:\nnoop\nconditonallySkipXops a").err(), Some("line 3: conditonallySkipXops can only jump forward".to_string()));
+    assert_eq!(disasm::assemble("instantiate 1 2").err(), Some("line 1: Unexpected 2".to_string()));
+}
+
+fn verify_asm(functions: Vec<(&str, &str)>) -> Result<(), String> {
+    let fns: HashMap<String, Vec<Op>> = functions.into_iter()
+        .map(|(name, asm)| (name.to_string(), disasm::assemble(asm).unwrap()))
+        .collect();
+    tuna_interpreter::verify::verify(&fns, &HashMap::new())
+}
+
+#[test]
+fn verifier_accepts_compiled_programs() {
+    let ex = tuna_compiler::compile(ARTIFACT_PROGRAM).unwrap();
+    assert_eq!(tuna_interpreter::verify::verify(&ex.fns, &ex.lambdas), Ok(()));
+    let ex = tuna_compiler::compile(r#"
+    func f(o) {
+        let total = 0
+        for k v in o {
+            if k == 'skip' {
+                total = total - 1
+            } else {
+                total = total + v
+            }
+        }
+        return total
+    }"#).unwrap();
+    assert_eq!(tuna_interpreter::verify::verify(&ex.fns, &ex.lambdas), Ok(()));
+}
+
+#[test]
+fn unpacking_checks_for_pairs() {
+    assert!(disasm::assemble("flattenArray").is_err());
+    let ops = disasm::assemble("instantiate []\nunpackPair\ngetField {\"field_depth\": 1}\nreturnStackTop").unwrap();
+    let fns = HashMap::from([("f".to_string(), ops)]);
+    assert_eq!(tuna_interpreter::verify::verify(&fns, &HashMap::new()), Ok(()));
+    let (schemas, lambdas) = (HashMap::new(), HashMap::new());
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    let (priv_key, pub_key) = ed25519::keypair(&key);
+    let g = tuna_interpreter::Globals::new(&schemas, &fns, &lambdas, &priv_key, &pub_key).unwrap();
+    assert_eq!(g.run(&"f".to_string(), &mut State::new(&mut vec![])), Err("Expected a [key, value] pair".to_string()));
+}
+
+#[test]
+fn unverified_closures_with_too_many_captures_are_errors() {
+    let fns = HashMap::from([
+        ("f".to_string(), disasm::assemble("instantiate 1\nmakeClosure {\"name\": \"g\", \"captures\": 1}\ninvokeValue {\"args\": 0}\nreturnStackTop").unwrap()),
+        ("g".to_string(), disasm::assemble("assertHeapLen 0\nreturnVoid").unwrap())
+    ]);
+    let (schemas, lambdas) = (HashMap::new(), HashMap::new());
+    let (priv_key, pub_key) = keypair();
+    let g = tuna_interpreter::Globals {
+        schemas: &schemas,
+        fns: &fns,
+        lambdas: &lambdas,
+        keys: roles::Keyring::new(priv_key, pub_key)
+    };
+    assert_eq!(g.run(&"f".to_string(), &mut State::new(&mut vec![])), Err("Function g captures more values than it takes".to_string()));
+}
+
+#[test]
+fn verifier_rejects_malformed_ops() {
+    assert_eq!(verify_asm(vec![("f", "instantiate 1\nplus\nreturnStackTop")]),
+        Err("f op 1 (plus): needs 2 value(s) on the stack but there are only 1".to_string()));
+    assert_eq!(verify_asm(vec![("f", "getField {\"field_depth\": 3}")]),
+        Err("f op 0 (getField): needs 4 value(s) on the stack but there are only 0".to_string()));
+    assert_eq!(verify_asm(vec![("f", "noop\noffsetOpCursor {\"offset\": 5, \"fwd\": false}")]),
+        Err("f op 1 (offsetOpCursor): jumps outside the function".to_string()));
+    assert_eq!(verify_asm(vec![("f", "instantiate true\nconditonallySkipXops 4")]),
+        Err("f op 1 (conditonallySkipXops): jumps outside the function".to_string()));
+    assert_eq!(verify_asm(vec![("f", "assertHeapLen 1\ncopyFromHeap 1")]),
+        Err("f op 1 (copyFromHeap): uses heap slot 1 but only 1 are in scope".to_string()));
+    assert_eq!(verify_asm(vec![("f", "instantiate 1\nmoveStackTopToHeap\ntruncateHeap 2")]),
+        Err("f op 2 (truncateHeap): drops 2 heap slot(s) but only 1 are in scope".to_string()));
+    assert_eq!(verify_asm(vec![("f", "instantiate 1\ninvoke {\"name\": \"g\", \"args\": 1}")]),
+        Err("f op 1 (invoke): invokes unknown function g".to_string()));
+    assert_eq!(verify_asm(vec![("f", "invoke {\"name\": \"g\", \"args\": 0}"), ("g", "assertHeapLen 1")]),
+        Err("f op 0 (invoke): passes 0 argument(s) to g which takes 1".to_string()));
+    assert_eq!(verify_asm(vec![("f", "instantiate true\nconditonallySkipXops end\ninstantiate 1\nend:\nreturnVoid")]),
+        Err("f op 2 (instantiate): reaches op 3 with 1 stack value(s) and 0 heap slot(s), but another path has 0 and 0".to_string()));
+    // Unreachable ops aren't checked.
+    assert_eq!(verify_asm(vec![("f", "returnVoid\npopStack")]), Ok(()));
+}
+
+#[test]
+fn rejected_programs_never_run() {
+    let fns = HashMap::from([
+        ("ok".to_string(), disasm::assemble("returnVoid").unwrap()),
+        ("bad".to_string(), disasm::assemble("popStack").unwrap())
+    ]);
+    let schemas = HashMap::new();
+    let (priv_key, pub_key) = ed25519::keypair(&[7u8; 32]);
+    assert_eq!(tuna_interpreter::Globals::new(&schemas, &fns, &HashMap::new(), &priv_key, &pub_key).err(),
+        Some("Program failed verification: bad op 0 (popStack): needs 1 value(s) on the stack but there are only 0".to_string()));
+}
+
+#[test]
+fn unknown_functions_are_errors() {
+    assert_eq!(try_run("func f() {}", "g", vec![]), Err("Unknown function g".to_string()));
+}
+
+fn listing(code: &str, func: &str) -> String {
+    let ex = tuna_compiler::compile(code).unwrap();
+    disasm::disassemble(&ex.fns[func], &[])
+}
+
+#[test]
+fn folds_constant_expressions() {
+    assert_eq!(listing("func f() { return 2 * 3 + 1 }", "f"), "     0  assertHeapLen 0\n     1  instantiate 7\n     2  returnStackTop\n");
+    assert_eq!(listing("func f() { return 1 < 2 and not false }", "f"), "     0  assertHeapLen 0\n     1  instantiate true\n     2  returnStackTop\n");
+    assert_eq!(try_run("func f() { return 'a' + 1 + 2.5 }", "f", vec![]), Ok(Data::string("a12.5".to_string())));
+    // Ops that would fail are left to fail when they run.
+    assert_eq!(try_run("func f() { return 1 / 0 }", "f", vec![]), Err("Division by zero".to_string()));
+}
+
+#[test]
+fn removes_dead_code_and_noops() {
+    let code = r#"
+    func f(a) {
+        if a == 1 {
+            return 1
+        } else {
+            return 2
+        }
+        return 3
+    }"#;
+    let ops = listing(code, "f");
+    assert!(!ops.contains("instantiate 3"));
+    assert!(!ops.contains("noop"));
+    assert_eq!(try_run(code, "f", vec![Data::int(1)]), Ok(Data::int(1)));
+    assert_eq!(try_run(code, "f", vec![Data::int(5)]), Ok(Data::int(2)));
+    // Branches on a literal condition keep only the branch that's taken.
+    let ops = listing("func f() { if false { return 1 } return 2 }", "f");
+    assert!(!ops.contains("instantiate 1\n"));
+    assert!(!ops.contains("conditonallySkipXops"));
+}
+
+#[test]
+fn branches_test_negated_comparisons_directly() {
+    let code = r#"
+    func f(a, b) {
+        if a != b {
+            return 'different'
+        }
+        return 'same'
+    }"#;
+    let ops = listing(code, "f");
+    assert!(ops.contains("equal\n"));
+    assert!(!ops.contains("negatePrev"));
+    assert_eq!(try_run(code, "f", vec![Data::int(1), Data::int(2)]), Ok(Data::string("different".to_string())));
+    assert_eq!(try_run(code, "f", vec![Data::int(1), Data::int(1)]), Ok(Data::string("same".to_string())));
+}
+
+#[test]
+fn and_or_skip_the_right_side_when_the_left_decides() {
+    // explode fails if it runs, so a result means the right side was skipped.
+    let code = r#"
+    func explode() {
+        return 1 / 0 > 0
+    }
+    func and_explode(a) {
+        return a and explode()
+    }
+    func or_explode(a) {
+        return a or explode()
+    }
+    func and_value(a, b) {
+        return a and b
+    }
+    func or_value(a, b) {
+        return a or b
+    }
+    func big(x) {
+        return x != none and x.f > 1
+    }"#;
+    with_globals(code, |run| {
+        let boom = Err("Division by zero".to_string());
+        assert_eq!(run("and_explode", vec![Data::bool(false)]), Ok(Data::bool(false)));
+        assert_eq!(run("and_explode", vec![Data::bool(true)]), boom);
+        assert_eq!(run("or_explode", vec![Data::bool(true)]), Ok(Data::bool(true)));
+        assert_eq!(run("or_explode", vec![Data::bool(false)]), boom);
+        for a in [false, true] {
+            for b in [false, true] {
+                assert_eq!(run("and_value", vec![Data::bool(a), Data::bool(b)]), Ok(Data::bool(a && b)));
+                assert_eq!(run("or_value", vec![Data::bool(a), Data::bool(b)]), Ok(Data::bool(a || b)));
+            }
+        }
+        assert_eq!(run("and_value", vec![Data::bool(true), Data::int(1)]), Err("Expected a boolean value".to_string()));
+        assert_eq!(run("big", vec![Data::None]), Ok(Data::bool(false)));
+        assert_eq!(run("big", vec![obj(vec![("f", Data::int(2))])]), Ok(Data::bool(true)));
+        assert_eq!(run("big", vec![obj(vec![("f", Data::int(0))])]), Ok(Data::bool(false)));
+    });
+}
+
+#[test]
+fn while_loops_run_until_the_condition_fails() {
+    let code = r#"
+    func count_down(n) {
+        let steps = []
+        while n > 0 {
+            let next = n - 1
+            steps.push(n)
+            n = next
+        }
+        return steps
+    }"#;
+    assert_eq!(try_run(code, "count_down", vec![Data::int(3)]), Ok(Data::Array(vec![Data::int(3), Data::int(2), Data::int(1)])));
+    assert_eq!(try_run(code, "count_down", vec![Data::int(0)]), Ok(Data::Array(vec![])));
+    let errs = tuna_compiler::compile("func f() { while 1 { } }").err().unwrap();
+    assert_eq!(errs[0].message, "Expected bool for a condition but found int");
+}
+
+#[test]
+fn break_and_continue_leave_loop_scopes() {
+    let code = r#"
+    func first_big(arr) {
+        let found = none
+        for i x in arr {
+            let doubled = x * 2
+            if doubled > 10 {
+                let hit = i
+                found = hit
+                break
+            }
+        }
+        let after = 'after'
+        return [found, after]
+    }
+    func odds(limit) {
+        let odds = []
+        let n = 0
+        while true {
+            let current = n
+            n = n + 1
+            if current >= limit {
+                break
+            }
+            if current == current / 2 * 2 {
+                continue
+            }
+            odds.push(current)
+        }
+        return odds
+    }
+    func pairs(n) {
+        let out = []
+        for a in n {
+            for b in n {
+                if b > a {
+                    break
+                }
+                if b == a {
+                    continue
+                }
+                out.push([a, b])
+            }
+        }
+        return out
+    }"#;
+    let ex = tuna_compiler::compile(code).unwrap();
+    assert_eq!(tuna_interpreter::verify::verify(&ex.fns, &ex.lambdas), Ok(()));
+    with_compiled(&ex, |run| {
+        let ints = |v: Vec<i64>| Data::Array(v.into_iter().map(Data::int).collect());
+        assert_eq!(run("first_big", vec![ints(vec![1, 6, 9])]), Ok(Data::Array(vec![Data::int(1), Data::string("after".to_string())])));
+        assert_eq!(run("first_big", vec![ints(vec![1])]), Ok(Data::Array(vec![Data::None, Data::string("after".to_string())])));
+        assert_eq!(run("odds", vec![Data::int(8)]), Ok(ints(vec![1, 3, 5, 7])));
+        assert_eq!(run("pairs", vec![ints(vec![0, 1, 2])]), Ok(Data::Array(vec![ints(vec![1, 0]), ints(vec![2, 0]), ints(vec![2, 1])])));
+    });
+}
+
+#[test]
+fn break_and_continue_must_be_in_a_loop() {
+    let errs = tuna_compiler::compile(r#"
+    func f() {
+        break
+        if true {
+            continue
+        }
+        while false {
+            break
+        }
+    }"#).err().unwrap();
+    assert_eq!(errs.len(), 2);
+    assert!(errs.iter().all(|e| e.code.code() == "E0012"));
+    assert_eq!(errs[0].message, "break can only be used inside a loop");
+}
+
+const CLOSURES: &str = r#"
+    func apply(f: func, x) {
+        return f(x)
+    }
+    func twice(x) {
+        return x * 2
+    }
+    func scale(arr, k) {
+        let times = (x) => x * k
+        let out = []
+        for x in arr {
+            out.push(times(x))
+        }
+        return out
+    }
+    func named(x) {
+        return apply(twice, x)
+    }
+    func inline(x) {
+        return apply((y) => y + 1, x)
+    }
+    func adder(n) {
+        return (x) => x + n
+    }
+    func use_adder() {
+        let add = adder(10)
+        return add(5)
+    }
+    func nested(a) {
+        let f = (b) => {
+            let g = (c) => a + b + c
+            return g(1)
+        }
+        return f(2)
+    }
+    func snapshot() {
+        let n = 1
+        let f = () => n
+        n = 5
+        return [f(), n]
+    }
+    func sign(x) {
+        let describe = (y: int) => {
+            if y < 0 {
+                return 'negative'
+            }
+            return 'positive'
+        }
+        return describe(x)
+    }
+    func too_many() {
+        let f = (x) => x
+        return f(1, 2)
+    }
+    func in_object() {
+        return {f: twice}
+    }"#;
+
+#[test]
+fn lambdas_capture_enclosing_variables() {
+    let ex = tuna_compiler::compile(CLOSURES).unwrap();
+    assert_eq!(tuna_interpreter::verify::verify(&ex.fns, &ex.lambdas), Ok(()));
+    with_compiled(&ex, |run| {
+        let ints = |v: Vec<i64>| Data::Array(v.into_iter().map(Data::int).collect());
+        assert_eq!(run("scale", vec![ints(vec![1, 2]), Data::int(3)]), Ok(ints(vec![3, 6])));
+        assert_eq!(run("named", vec![Data::int(4)]), Ok(Data::int(8)));
+        assert_eq!(run("inline", vec![Data::int(4)]), Ok(Data::int(5)));
+        assert_eq!(run("use_adder", vec![]), Ok(Data::int(15)));
+        assert_eq!(run("nested", vec![Data::int(10)]), Ok(Data::int(13)));
+        // Values are captured when the lambda is made.
+        assert_eq!(run("snapshot", vec![]), Ok(ints(vec![1, 5])));
+        assert_eq!(run("sign", vec![Data::int(-3)]), Ok(Data::string("negative".to_string())));
+        assert_eq!(run("sign", vec![Data::string("3".to_string())]), Err("Input did not match expectations for y".to_string()));
+        assert_eq!(run("apply", vec![Data::int(1), Data::int(2)]), Err("Input did not match expectations for f".to_string()));
+        assert_eq!(run("too_many", vec![]), Err("Function takes 1 argument(s) but 2 were given".to_string()));
+    });
+}
+
+#[test]
+fn functions_cannot_leave_the_program() {
+    with_globals(CLOSURES, |run| {
+        assert_eq!(run("adder", vec![Data::int(1)]), Err("adder returned a function, which can't leave the program".to_string()));
+        assert_eq!(run("in_object", vec![]), Err("in_object returned a function, which can't leave the program".to_string()));
+    });
+    let closure = Data::Function(tuna_interpreter::data::Closure {name: "twice".to_string(), captured: vec![]});
+    assert!(serde_json::to_string(&closure).is_err());
+
+    let errs = tuna_compiler::compile(r#"
+    role Holder {f: any}
+    pub func leak() {
+        return {f: (x) => x}
+    }
+    func sign() {
+        return Holder {f: (x) => x}
+    }"#).err().unwrap();
+    let messages: Vec<&str> = errs.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec!["pub functions respond with JSON, so can't return functions", "Role Holder cannot hold functions"]);
+}
+
+#[test]
+fn lambdas_capture_only_what_they_use() {
+    let depth = 24;
+    let code = format!(r#"
+    func shadow(x) {{
+        let f = (y) => {{
+            let x = y
+            return x
+        }}
+        return f(2)
+    }}
+    func deep(a) {{
+        let f = {}a
+        return f()
+    }}"#, "() => ".repeat(depth));
+    let ex = tuna_compiler::compile(&code).unwrap();
+    let closures = |func: &str| -> Vec<(String, u64)> {
+        ex.fns.get(func).or_else(|| ex.lambdas.get(func)).unwrap().iter().filter_map(|op| match op {
+            Op::makeClosure{name, captures} => Some((name.clone(), *captures)),
+            _ => None
+        }).collect()
+    };
+    assert_eq!(closures("shadow"), vec![("shadow#0".to_string(), 0)]);
+    assert_eq!(closures("deep"), vec![("deep#0".to_string(), 1)]);
+    assert_eq!(ex.lambdas.keys().filter(|name| name.starts_with("deep")).count(), depth);
+    assert_eq!(closures(&format!("deep{}", "#0".repeat(depth - 1))), vec![(format!("deep{}", "#0".repeat(depth)), 1)]);
+    with_compiled(&ex, |run| assert_eq!(run("shadow", vec![Data::int(1)]), Ok(Data::int(2))));
+}
+
+#[test]
+fn lambdas_are_not_entry_points() {
+    let ex = tuna_compiler::compile(CLOSURES).unwrap();
+    assert!(ex.lambdas.contains_key("scale#0"));
+    assert!(!ex.fns.contains_key("scale#0"));
+    // Calling a lambda directly would let callers pick the values it captured.
+    assert_eq!(try_run(CLOSURES, "scale#0", vec![Data::int(1), Data::int(2)]), Err("Unknown function scale#0".to_string()));
+
+    let source = std::env::temp_dir().join(format!("tuna-lambdas-{}.tuna", std::process::id()));
+    std::fs::write(&source, CLOSURES).unwrap();
+    let (ok, _, err) = tuna(&["run", source.to_str().unwrap(), "scale#0", "--args", "[1, 2]"]);
+    std::fs::remove_file(&source).unwrap();
+    assert!(!ok);
+    assert!(err.contains("Unknown function scale#0"));
+}
+
+#[test]
+fn only_functions_can_be_called() {
+    let errs = tuna_compiler::compile(r#"
+    func f() {
+        let x = 1
+        return x(2)
+    }
+    func g(arr) {
+        for a in arr {
+            let h = () => {
+                break
+            }
+        }
+    }"#).err().unwrap();
+    let messages: Vec<&str> = errs.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec!["break can only be used inside a loop", "Cannot call int"]);
+    assert_eq!(try_run("func f(x) { return x(1) }", "f", vec![Data::int(1)]), Err("Only functions can be called".to_string()));
+}
+
+#[test]
+fn array_methods_take_lambdas_and_named_functions() {
+    let code = r#"
+    func double(x) {
+        return x * 2
+    }
+    func is_even(x) {
+        return x == x / 2 * 2
+    }
+    func f(arr, nested) {
+        let limit = 2
+        return [
+            arr.map(double),
+            arr.map((x) => x + limit),
+            arr.filter(is_even),
+            arr.reduce((sum, x) => sum + x, 0),
+            arr.find((x) => x > limit),
+            arr.find((x) => x > 100),
+            arr.any(is_even),
+            arr.all((x) => x > 0),
+            arr.sort(),
+            arr.sort((a, b) => b - a),
+            arr.slice(1),
+            arr.slice(1, 2),
+            arr.slice(2, 100),
+            arr.concat([7, 8]),
+            arr.reverse(),
+            arr.index_of(1),
+            arr.index_of(9),
+            nested.flatten(),
+            arr.map((x) => [x]).flatten(),
+            ['b', 'c', 'a'].sort(),
+            [].all(is_even)
+        ]
+    }"#;
+    let ints = |v: Vec<i64>| Data::Array(v.into_iter().map(Data::int).collect());
+    let nested = Data::Array(vec![ints(vec![1, 2]), Data::int(3), Data::Array(vec![ints(vec![4])])]);
+    assert_eq!(try_run(code, "f", vec![ints(vec![3, 1, 4, 2]), nested]), Ok(Data::Array(vec![
+        ints(vec![6, 2, 8, 4]),
+        ints(vec![5, 3, 6, 4]),
+        ints(vec![4, 2]),
+        Data::int(10),
+        Data::int(3),
+        Data::None,
+        Data::bool(true),
+        Data::bool(true),
+        ints(vec![1, 2, 3, 4]),
+        ints(vec![4, 3, 2, 1]),
+        ints(vec![1, 4, 2]),
+        ints(vec![1]),
+        ints(vec![4, 2]),
+        ints(vec![3, 1, 4, 2, 7, 8]),
+        ints(vec![2, 4, 1, 3]),
+        Data::int(1),
+        Data::int(-1),
+        Data::Array(vec![Data::int(1), Data::int(2), Data::int(3), ints(vec![4])]),
+        ints(vec![3, 1, 4, 2]),
+        Data::Array(vec![Data::string("a".to_string()), Data::string("b".to_string()), Data::string("c".to_string())]),
+        Data::bool(true)
+    ])));
+}
+
+#[test]
+fn array_methods_report_bad_use() {
+    let errs = tuna_compiler::compile(r#"
+    func f(a: int, arr: int[]) {
+        let x = a.map((y) => y)
+        let y = arr.filter(1)
+        let z = arr.sort(1, 2)
+    }"#).err().unwrap();
+    let messages: Vec<&str> = errs.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "sort() takes 0 to 1 argument(s) but 2 were given",
+        "Cannot call map() on int",
+        "Expected function for the argument of filter() but found int",
+        "Expected function for the argument of sort() but found int"
+    ]);
+
+    with_globals(r#"
+    func mixed(arr) {
+        return arr.sort()
+    }
+    func not_bool(arr) {
+        return arr.filter((x) => x)
+    }
+    func fails(arr) {
+        return arr.map((x) => 1 / x)
+    }"#, |run| {
+        assert_eq!(run("mixed", vec![Data::Array(vec![Data::int(1), Data::string("a".to_string())])]),
+            Err("Can only sort numbers or strings without a comparator".to_string()));
+        assert_eq!(run("not_bool", vec![Data::Array(vec![Data::int(1)])]), Err("Expected a boolean value".to_string()));
+        assert_eq!(run("fails", vec![Data::Array(vec![Data::int(1), Data::int(0)])]), Err("Division by zero".to_string()));
+    });
+}
+
+#[test]
+fn sorting_survives_any_comparator() {
+    with_globals(r#"
+    func always(arr) {
+        return arr.sort((a, b) => 1)
+    }
+    func stable(arr) {
+        return arr.sort((a, b) => a[0] - b[0])
+    }
+    func fails(arr) {
+        return arr.sort((a, b) => 1 / (a - b - 2))
+    }"#, |run| {
+        let ints = |v: Vec<i64>| Data::Array(v.into_iter().map(Data::int).collect());
+        let sorted = run("always", vec![ints((0..40).collect())]).unwrap().to_array().unwrap();
+        assert_eq!(sorted.len(), 40);
+        let pairs = Data::Array(vec![ints(vec![1, 0]), ints(vec![0, 1]), ints(vec![1, 2]), ints(vec![0, 3])]);
+        assert_eq!(run("stable", vec![pairs]), Ok(Data::Array(vec![ints(vec![0, 1]), ints(vec![0, 3]), ints(vec![1, 0]), ints(vec![1, 2])])));
+        assert_eq!(run("fails", vec![ints(vec![4, 3, 2, 1, 0])]), Err("Division by zero".to_string()));
+    });
+}
+
+#[test]
+fn array_methods_do_not_shadow_functions_silently() {
+    let errs = tuna_compiler::compile(r#"
+    func find(arr, x) {
+        return x
+    }
+    func f(arr) {
+        return arr.find((y) => y == 1)
+    }"#).err().unwrap();
+    assert_eq!(errs.len(), 1);
+    assert_eq!("E0013", errs[0].code.code());
+    assert_eq!(errs[0].message, "find() is a built in method, so it can't call function find; call find(x, ...) instead");
+}